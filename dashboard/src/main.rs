@@ -1,173 +1,4420 @@
 use axum::{
-    extract::State,
-    response::Html,
-    routing::get,
-    Router,
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Query, State,
+    },
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
 };
-use rumqttc::{MqttOptions, AsyncClient, QoS, Event, Packet};
-use serde::Deserialize;
-use std::{sync::{Arc, Mutex}, time::Duration};
-use chrono::Local;
+use rumqttc::{MqttOptions, AsyncClient, EventLoop, QoS, Event, Packet, Transport, LastWill};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::{broadcast, watch};
+use rusqlite::Connection;
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::EnvFilter;
+use tower_http::compression::{
+    predicate::{NotForContentType, Predicate, SizeAbove},
+    CompressionLayer,
+};
+use tower_http::decompression::RequestDecompressionLayer;
+use tower_http::trace::{DefaultMakeSpan, DefaultOnResponse, TraceLayer};
+use tower_http::LatencyUnit;
+use chrono::{DateTime, FixedOffset, Local, NaiveTime, SecondsFormat, TimeDelta};
+use plotters::prelude::*;
+
+// Quantidade máxima de registros mantidos no histórico (sobrescrita por HISTORY_CAP)
+const HISTORICO_MAX_PADRAO: usize = 10;
+const ENV_HISTORY_CAP: &str = "HISTORY_CAP";
+// Retenção por tempo: descarta leituras mais antigas que N minutos (desligada se ausente)
+const ENV_HISTORY_MAX_AGE: &str = "HISTORY_MAX_AGE_MINUTES";
+
+// Arquivo SQLite onde as leituras são persistidas (sobrescrito por SQLITE_PATH)
+const ENV_SQLITE_PATH: &str = "SQLITE_PATH";
+const SQLITE_PATH_PADRAO: &str = "leituras.db";
+
+// Leituras pendentes por cliente do /ws antes de descartar as mais antigas
+const AO_VIVO_CAPACIDADE_CANAL: usize = 16;
+
+// Faixas de validação padrão (min, max), sobrescritas por FAIXA_TEMPERATURA/UMIDADE/PRESSAO
+const FAIXA_TEMPERATURA_PADRAO: (f64, f64) = (-40.0, 85.0);
+const FAIXA_UMIDADE_PADRAO: (f64, f64) = (0.0, 100.0);
+const FAIXA_PRESSAO_PADRAO: (f64, f64) = (300.0, 1100.0);
+
+// Cor dos valores nos cards: verde dentro da faixa de atenção, âmbar fora dela e vermelho
+// fora da faixa crítica. Padrões para ambiente interno, sobrescritos por
+// COR_TEMPERATURA_ATENCAO/_CRITICO e COR_UMIDADE_ATENCAO/_CRITICO ("min,max")
+const COR_TEMPERATURA_ATENCAO_PADRAO: (f64, f64) = (18.0, 28.0);
+const COR_TEMPERATURA_CRITICO_PADRAO: (f64, f64) = (15.0, 32.0);
+const COR_UMIDADE_ATENCAO_PADRAO: (f64, f64) = (40.0, 60.0);
+const COR_UMIDADE_CRITICO_PADRAO: (f64, f64) = (30.0, 70.0);
+
+// Diferença máxima aceitável entre as temperaturas do BMP280 e do DHT11
+const DIVERGENCIA_TEMPERATURA_MAX_C: f64 = 2.0;
+
+// Coeficientes da fórmula de Magnus para o ponto de orvalho
+const MAGNUS_A: f64 = 17.27;
+const MAGNUS_B: f64 = 237.7;
+// Pressão de vapor de saturação a 0 °C (hPa) e faixa de temperatura em que Magnus vale (°C)
+const MAGNUS_ES0_HPA: f64 = 6.112;
+const MAGNUS_FAIXA_C: (f64, f64) = (-45.0, 60.0);
+// Mw / R em g·K/(m³·hPa): converte pressão de vapor em umidade absoluta
+const UMIDADE_ABSOLUTA_K: f64 = 216.7;
+
+// Redução da pressão ao nível do mar (fórmula barométrica da atmosfera padrão):
+// gradiente térmico (K/m) e expoente g·M/(R·L)
+const GRADIENTE_TERMICO_K_POR_M: f64 = 0.0065;
+const EXPOENTE_BAROMETRICO: f64 = 5.257;
+// Referência padrão do firmware (`PRESSAO_REFERENCIA_HPA`) para estimar a altitude em
+// /download/raw quando a leitura não trouxe `altitude`
+const ALTITUDE_REFERENCIA_HPA: f64 = 1013.25;
+// Fórmula hipsométrica do `calcular_altitude` do firmware:
+// h = ALTITUDE_ESCALA_M · (1 − (P / P0)^ALTITUDE_EXPOENTE)
+const ALTITUDE_ESCALA_M: f64 = 44330.0;
+const ALTITUDE_EXPOENTE: f64 = 0.1903;
+
+// POST /api/ingest: timestamps do firmware abaixo disso (2024-01-01T00:00:00Z) são uptime,
+// gravados antes da sincronização do relógio, e não têm como ser posicionados no tempo
+const EPOCH_MINIMO_VALIDO: i64 = 1_704_067_200;
+// Leitura enviada a até N segundos de outra já conhecida, com a mesma grandeza, é duplicata
+// (a mesma leitura chega pelo MQTT com o horário de chegada, não o do dispositivo)
+const INGESTAO_TOLERANCIA_SEGUNDOS: i64 = 30;
+
+// Abaixo disso a regressão de Rothfusz não vale e a sensação térmica é a própria temperatura
+const HEAT_INDEX_MIN_C: f64 = 27.0;
+
+// Variação de pressão na janela (hPa) a partir da qual a tendência deixa de ser estável
+const TENDENCIA_PRESSAO_LIMIAR_HPA: f64 = 1.0;
+// Desvio padrão da pressão na janela (hPa) acima do qual a tendência é pouco confiável;
+// a barra de volatilidade enche no dobro disso
+const VOLATILIDADE_PRESSAO_LIMIAR_HPA: f64 = 0.5;
+
+// Fatores de conversão da pressão exibida (?punit=); os dados ficam sempre em hPa
+const HPA_PARA_INHG: f64 = 0.02953;
+const HPA_PARA_MMHG: f64 = 0.75006;
+
+// Zona de conforto para ambientes internos (°C e % de umidade relativa).
+// Fora da faixa de temperatura o veredito é Quente/Frio, mesmo com a umidade ideal.
+const CONFORTO_TEMPERATURA_C: (f64, f64) = (20.0, 26.0);
+const CONFORTO_UMIDADE: (f64, f64) = (30.0, 60.0);
+
+// Dimensões (viewBox) do gráfico SVG de temperatura no dashboard
+const GRAFICO_LARGURA: f64 = 600.0;
+const GRAFICO_ALTURA: f64 = 150.0;
+const GRAFICO_MARGEM: f64 = 15.0;
+// Colunas da decimação mín/máx do gráfico do dashboard: uma a cada 2 px da área útil
+const GRAFICO_COLUNAS: usize = ((GRAFICO_LARGURA - 2.0 * GRAFICO_MARGEM) / 2.0) as usize;
+
+// Paleta escura: sobrescreve só as cores do tema claro (fundo, cards, tabela, lista, gráfico)
+const CSS_TEMA_ESCURO: &str = "
+    body { background: #181a1f; color: #e0e0e0; }
+    a { color: #8ab4f8; }
+    .card, .grafico, table, ul.lista { background: #262a31; box-shadow: 0 2px 5px rgba(0,0,0,0.5); }
+    .ts, .grafico text { color: #999; fill: #999; }
+    .divergencia, .estatisticas, .tendencia { color: #aaa; }
+    .volatilidade { background: #3a3f47; }
+    .congelado { background: #1f3b57; }
+    th { background-color: #111; }
+    th, td, ul.lista li { border-bottom-color: #3a3f47; }
+    tr:nth-child(even) { background-color: #2c3038; }
+";
+
+// Broker MQTT (sobrescrito por MQTT_HOST/MQTT_PORT; MQTT_TLS=1 liga TLS)
+const MQTT_HOST_PADRAO: &str = "test.mosquitto.org";
+const MQTT_PORTA_PADRAO: u16 = 1883;
+const MQTT_PORTA_TLS_PADRAO: u16 = 8883;
+const ENV_MQTT_HOST: &str = "MQTT_HOST";
+const ENV_MQTT_PORT: &str = "MQTT_PORT";
+const ENV_MQTT_TLS: &str = "MQTT_TLS";
+
+// QoS das assinaturas de leituras e heartbeat, 0 a 2 (sobrescrito por MQTT_QOS)
+const MQTT_QOS_PADRAO: u8 = 1;
+const ENV_MQTT_QOS: &str = "MQTT_QOS";
+
+// Credenciais do broker; sem as duas a conexão é anônima
+const ENV_MQTT_USER: &str = "MQTT_USER";
+const ENV_MQTT_PASS: &str = "MQTT_PASS";
+
+// Status do servidor (retido): `online` ao conectar, `offline` pelo testamento (LWT) se a conexão cair
+// ou ao encerrar normalmente (sobrescrito por MQTT_STATUS_TOPIC)
+const TOPICO_STATUS_PADRAO: &str = "sensores/status";
+const ENV_MQTT_STATUS_TOPIC: &str = "MQTT_STATUS_TOPIC";
+const STATUS_ONLINE: &str = "online";
+const STATUS_OFFLINE: &str = "offline";
+
+// Espera entre tentativas de reconexão ao broker: dobra a cada falha até o teto
+const MQTT_BACKOFF_INICIAL: Duration = Duration::from_secs(1);
+const MQTT_BACKOFF_MAX: Duration = Duration::from_secs(30);
+// Folga do canal de requisições do AsyncClient além das assinaturas iniciais. As assinaturas
+// são enfileiradas antes de o eventloop rodar, então o canal precisa caber todas elas.
+const MQTT_CANAL_FOLGA: usize = 10;
+// Espera antes de reiniciar o loop MQTT depois de um panic
+const MQTT_REINICIO_APOS_PANIC: Duration = Duration::from_secs(5);
+
+// Leituras chegam em `sensores/rusty_weather/<dispositivo>`; o nível coberto pelo `+` identifica
+// o ESP32 (sobrescrito por MQTT_TOPIC). O prefixo do projeto evita que, no broker público,
+// qualquer `sensores/<algo>` de terceiros ocupe as vagas de DISPOSITIVOS_MAX.
+const TOPICO_LEITURAS_PADRAO: &str = "sensores/rusty_weather/+";
+const ENV_MQTT_TOPIC: &str = "MQTT_TOPIC";
+
+// Métricas derivadas (ponto de orvalho, sensação térmica, tendência da pressão) republicadas
+// a cada leitura para outros assinantes, como o Home Assistant (sobrescrito por MQTT_DERIVED_TOPIC)
+const TOPICO_DERIVADAS_PADRAO: &str = "sensores/esp32/derived";
+const ENV_MQTT_DERIVED_TOPIC: &str = "MQTT_DERIVED_TOPIC";
+
+// Porta do servidor HTTP (sobrescrita por HTTP_PORT)
+const PORTA_HTTP_PADRAO: u16 = 3000;
+const ENV_HTTP_PORT: &str = "HTTP_PORT";
+// Respostas menores que isso (bytes) saem sem compressão: o ganho não paga o custo
+const COMPRESSAO_TAMANHO_MIN_BYTES: u16 = 1024;
+// Nível de log quando RUST_LOG não está definida
+const NIVEL_LOG_PADRAO: &str = "info";
+// Nível do log de cada requisição HTTP (método, caminho, status e latência), sobrescrito
+// por LOG_REQUISICOES. Só aparece se o RUST_LOG também habilitar esse nível.
+const NIVEL_LOG_REQUISICOES_PADRAO: tracing::Level = tracing::Level::INFO;
+const ENV_LOG_REQUISICOES: &str = "LOG_REQUISICOES";
+// Dispositivo assumido quando a origem não é informada (?device= ausente, banco sem a coluna)
+const DISPOSITIVO_PADRAO: &str = "esp32";
+// O broker é público: limita quantos dispositivos distintos ocupam memória
+const DISPOSITIVOS_MAX: usize = 16;
+
+// Publicação periódica de agregados: desligada sem AGGREGATE_INTERVAL_MINUTES (o broker padrão é
+// público); o tópico é sobrescrito por MQTT_AGGREGATE_TOPIC
+const ENV_AGGREGATE_INTERVAL_MINUTES: &str = "AGGREGATE_INTERVAL_MINUTES";
+const ENV_MQTT_AGGREGATE_TOPIC: &str = "MQTT_AGGREGATE_TOPIC";
+const TOPICO_AGREGADO_PADRAO: &str = "sensores/rusty_weather/unb_211068459/agregado";
+
+// Comandos de OTA para os dispositivos
+const TOPICO_OTA: &str = "sensores/esp32/ota";
+// Tokens exigidos no header `Authorization: Bearer <token>`; sem eles as rotas ficam desativadas
+const ENV_OTA_TOKEN: &str = "OTA_TOKEN";
+const ENV_ADMIN_TOKEN: &str = "ADMIN_TOKEN";
+
+// Heartbeat do firmware: indica que o dispositivo está vivo mesmo sem leituras.
+// Um tópico por dispositivo; o `+` é o id, o mesmo do tópico das leituras.
+const TOPICO_HEARTBEAT: &str = "sensores/rusty_weather/+/heartbeat";
+// Sem heartbeat por mais tempo que isso, o dispositivo é considerado offline
+const HEARTBEAT_TIMEOUT_SEGUNDOS: i64 = 180;
+
+// Sem leituras por mais tempo que isso, o dispositivo ganha o selo OFFLINE (sobrescrito por OFFLINE_APOS_SEGUNDOS)
+const OFFLINE_APOS_SEGUNDOS_PADRAO: i64 = 30;
+const ENV_OFFLINE_APOS_SEGUNDOS: &str = "OFFLINE_APOS_SEGUNDOS";
+
+// /healthz responde 503 sem mensagens MQTT por mais tempo que isso (sobrescrito por HEALTHZ_MAX_SEGUNDOS).
+// O firmware publica heartbeat a cada 60 s.
+const HEALTHZ_MAX_SEGUNDOS_PADRAO: u64 = 180;
+const ENV_HEALTHZ_MAX_SEGUNDOS: &str = "HEALTHZ_MAX_SEGUNDOS";
+
+// Com DEDUP=1, leituras iguais à anterior do dispositivo (diferença ≤ DEDUP_EPSILON em
+// temperatura, umidade e pressão) não entram no histórico
+const ENV_DEDUP: &str = "DEDUP";
+const ENV_DEDUP_EPSILON: &str = "DEDUP_EPSILON";
+const DEDUP_EPSILON_PADRAO: f64 = 0.05;
+
+// Alertas por webhook: ALERTA_WEBHOOK_URL ativa; ALERTA_<CAMPO>_MIN/_MAX definem os limites
+// (CAMPO = TEMPERATURA ou UMIDADE, cada lado opcional). Só as mudanças de estado são enviadas.
+const ENV_ALERTA_WEBHOOK_URL: &str = "ALERTA_WEBHOOK_URL";
+const ALERTA_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Abaixo disso (V) o card da bateria fica vermelho; ~3,4 V é uma Li-ion quase vazia
+const BATERIA_BAIXA_V: f64 = 3.4;
+
+// Versão do formato de Registro/SensorData em snapshots exportados.
+// v1: temperatura, umidade, pressao
+// v2: + temperatura_dht11 (opcional)
+// v3: horario com data e fuso (RFC 3339) em vez de só "%H:%M:%S"
+// v4: umidade e pressao opcionais (null quando o dispositivo não tem o sensor)
+// v5: + bateria (opcional, V)
+// v6: + altitude (opcional, m)
+// Ao subir a versão, trate as anteriores em `Snapshot::migrar`.
+const SCHEMA_VERSION: u32 = 6;
+
+// Dados brutos que vêm do sensor
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+struct SensorData {
+    temperatura: f64,
+    // Ausentes em dispositivos sem o sensor correspondente (frotas mistas)
+    #[serde(default)]
+    umidade: Option<f64>,
+    #[serde(default)]
+    pressao: Option<f64>,
+    // Temperatura do DHT11, quando o firmware publica as duas fontes
+    #[serde(default)]
+    temperatura_dht11: Option<f64>,
+    // Tensão da bateria (V), em estações alimentadas por bateria/solar
+    #[serde(default)]
+    bateria: Option<f64>,
+    // Altitude calculada pelo firmware a partir do BMP280 (m)
+    #[serde(default)]
+    altitude: Option<f64>,
+}
+
+impl SensorData {
+    // Mesmas grandezas presentes e cada uma a no máximo `epsilon` da outra
+    fn repete(&self, anterior: &SensorData, epsilon: f64) -> bool {
+        let perto = |a: f64, b: f64| (a - b).abs() <= epsilon;
+        let perto_opcional = |a: Option<f64>, b: Option<f64>| match (a, b) {
+            (Some(a), Some(b)) => perto(a, b),
+            (None, None) => true,
+            _ => false,
+        };
+
+        perto(self.temperatura, anterior.temperatura)
+            && perto_opcional(self.umidade, anterior.umidade)
+            && perto_opcional(self.pressao, anterior.pressao)
+    }
+
+    // Pressão reduzida ao nível do mar; None sem pressão ou sem altitude
+    fn pressao_nivel_mar(&self) -> Option<f64> {
+        Some(to_sea_level(self.pressao?, self.altitude?, self.temperatura))
+    }
+}
+
+// Lê DEDUP e DEDUP_EPSILON; None quando a deduplicação está desligada (padrão)
+fn deduplicacao_from_env() -> Option<f64> {
+    if !matches!(std::env::var(ENV_DEDUP).as_deref(), Ok("1" | "true")) {
+        return None;
+    }
+    Some(env_ou_padrao(ENV_DEDUP_EPSILON, DEDUP_EPSILON_PADRAO).abs())
+}
+
+// Estrutura interna para guardar o dado + a hora que ele chegou
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct Registro {
+    dados: SensorData,
+    // Instante de chegada; formatado só na hora de exibir
+    #[serde(deserialize_with = "deserializar_horario")]
+    horario: DateTime<Local>,
+}
+
+// Aceita RFC 3339 ou o formato antigo "%H:%M:%S" (assumido como hoje)
+fn parse_horario(texto: &str) -> Option<DateTime<Local>> {
+    if let Ok(t) = DateTime::parse_from_rfc3339(texto) {
+        return Some(t.with_timezone(&Local));
+    }
+
+    let hora = NaiveTime::parse_from_str(texto, "%H:%M:%S").ok()?;
+    Local::now().date_naive().and_time(hora).and_local_timezone(Local).single()
+}
+
+fn deserializar_horario<'de, D: serde::Deserializer<'de>>(d: D) -> Result<DateTime<Local>, D::Error> {
+    let texto = String::deserialize(d)?;
+    parse_horario(&texto).ok_or_else(|| serde::de::Error::custom(format!("horario inválido: {:?}", texto)))
+}
+
+// Visão de Registro para a API JSON: campos do sensor no mesmo nível de `horario`
+#[derive(Serialize, Debug)]
+struct RegistroApi {
+    #[serde(flatten)]
+    dados: SensorData,
+    // "%H:%M:%S", como no dashboard
+    horario: String,
+    // Data e hora completas (RFC 3339)
+    timestamp: DateTime<Local>,
+    // Calculado a partir de temperatura e umidade (None sem umidade)
+    ponto_orvalho: Option<f64>,
+    sensacao_termica: Option<f64>,
+    umidade_absoluta: Option<f64>,
+    // `pressao` é a da estação; esta é reduzida ao nível do mar (None sem altitude)
+    pressao_nivel_mar: Option<f64>,
+    conforto: Conforto,
+}
+
+impl From<&Registro> for RegistroApi {
+    fn from(reg: &Registro) -> Self {
+        Self {
+            dados: reg.dados,
+            horario: reg.horario.format("%H:%M:%S").to_string(),
+            timestamp: reg.horario,
+            ponto_orvalho: reg.dados.umidade.map(|rh| dew_point(reg.dados.temperatura, rh)),
+            sensacao_termica: reg.dados.umidade.map(|rh| heat_index(reg.dados.temperatura, rh)),
+            umidade_absoluta: reg.dados.umidade.map(|rh| absolute_humidity(reg.dados.temperatura, rh)),
+            pressao_nivel_mar: reg.dados.pressao_nivel_mar(),
+            conforto: Conforto::classificar(reg.dados.temperatura, reg.dados.umidade),
+        }
+    }
+}
+
+// RegistroApi com nomes em inglês (?lang=en), para integrações. Só muda o formato da
+// resposta de /api/current e /api/history: o payload MQTT do firmware, o /ws e as
+// demais rotas JSON (/api/series, /api/stats, /api/aggregate, /api/devices,
+// /api/snapshot) continuam em português e ignoram o parâmetro.
+#[derive(Serialize, Debug)]
+struct RegistroApiEn {
+    #[serde(rename = "temperature")]
+    temperatura: f64,
+    #[serde(rename = "humidity")]
+    umidade: Option<f64>,
+    #[serde(rename = "pressure")]
+    pressao: Option<f64>,
+    #[serde(rename = "temperature_dht11")]
+    temperatura_dht11: Option<f64>,
+    #[serde(rename = "battery")]
+    bateria: Option<f64>,
+    altitude: Option<f64>,
+    #[serde(rename = "time")]
+    horario: String,
+    timestamp: DateTime<Local>,
+    #[serde(rename = "dew_point")]
+    ponto_orvalho: Option<f64>,
+    #[serde(rename = "heat_index")]
+    sensacao_termica: Option<f64>,
+    #[serde(rename = "absolute_humidity")]
+    umidade_absoluta: Option<f64>,
+    #[serde(rename = "sea_level_pressure")]
+    pressao_nivel_mar: Option<f64>,
+    #[serde(rename = "comfort")]
+    conforto: &'static str,
+}
+
+impl From<RegistroApi> for RegistroApiEn {
+    fn from(reg: RegistroApi) -> Self {
+        Self {
+            temperatura: reg.dados.temperatura,
+            umidade: reg.dados.umidade,
+            pressao: reg.dados.pressao,
+            temperatura_dht11: reg.dados.temperatura_dht11,
+            bateria: reg.dados.bateria,
+            altitude: reg.dados.altitude,
+            horario: reg.horario,
+            timestamp: reg.timestamp,
+            ponto_orvalho: reg.ponto_orvalho,
+            sensacao_termica: reg.sensacao_termica,
+            umidade_absoluta: reg.umidade_absoluta,
+            pressao_nivel_mar: reg.pressao_nivel_mar,
+            conforto: reg.conforto.chave_en(),
+        }
+    }
+}
+
+// ?lang=en em /api/current e /api/history; qualquer outro valor mantém os nomes em português
+#[derive(Deserialize, Debug, Default)]
+struct IdiomaParams {
+    lang: Option<String>,
+}
+
+impl IdiomaParams {
+    fn ingles(&self) -> bool {
+        self.lang.as_deref().is_some_and(|l| l.eq_ignore_ascii_case("en"))
+    }
+}
+
+impl RegistroApi {
+    // Pressão na unidade pedida com ?punit=; o resto não muda
+    fn com_pressao_em(mut self, unidade: UnidadePressao) -> Self {
+        self.dados.pressao = self.dados.pressao.map(|p| unidade.converter(p));
+        self.pressao_nivel_mar = self.pressao_nivel_mar.map(|p| unidade.converter(p));
+        self
+    }
+}
+
+// Linha do histórico já formatada, compartilhada pelas visualizações
+struct LinhaHistorico {
+    horario: String,
+    // Data e hora completas, exibidas como tooltip
+    data_hora: String,
+    temperatura: String,
+    umidade: String,
+    pressao: String,
+}
+
+impl LinhaHistorico {
+    fn from_registro(reg: &Registro, rotulos: &Rotulos, unidades: Unidades) -> Self {
+        Self {
+            horario: reg.horario.format("%H:%M:%S").to_string(),
+            data_hora: reg.horario.format("%d/%m/%Y %H:%M:%S").to_string(),
+            temperatura: format!("{:.1} {}", unidades.temperatura.converter(reg.dados.temperatura), rotulos.temperatura.unidade),
+            umidade: formatar_opcional(reg.dados.umidade, &rotulos.umidade.unidade),
+            pressao: formatar_pressao(reg.dados.pressao, unidades.pressao, &rotulos.pressao.unidade),
+        }
+    }
+}
+
+// Valor com uma casa decimal e unidade, ou "—" quando o dispositivo não mediu a grandeza
+fn formatar_opcional(valor: Option<f64>, unidade: &str) -> String {
+    match valor {
+        Some(v) if unidade.is_empty() => format!("{:.1}", v),
+        Some(v) => format!("{:.1} {}", v, unidade),
+        None => "—".to_string(),
+    }
+}
+
+// Como `formatar_opcional`, mas convertendo de hPa e com as casas decimais da unidade
+fn formatar_pressao(hpa: Option<f64>, unidade: UnidadePressao, rotulo: &str) -> String {
+    match hpa {
+        Some(p) if rotulo.is_empty() => unidade.formatar(p),
+        Some(p) => format!("{} {}", unidade.formatar(p), rotulo),
+        None => "—".to_string(),
+    }
+}
+
+// Nome exibido de uma grandeza: `curto` nos cards, `completo` no cabeçalho da tabela
+#[derive(Debug, Clone)]
+struct RotuloCampo {
+    curto: String,
+    completo: String,
+    unidade: String,
+}
+
+impl RotuloCampo {
+    // ROTULO_<CAMPO>, CABECALHO_<CAMPO> e UNIDADE_<CAMPO> sobrescrevem os padrões
+    fn from_env(campo: &str, curto: &str, completo: &str, unidade: &str) -> Self {
+        let var = |prefixo: &str, padrao: &str| {
+            std::env::var(format!("{}_{}", prefixo, campo)).unwrap_or_else(|_| padrao.to_string())
+        };
+
+        Self {
+            curto: var("ROTULO", curto),
+            completo: var("CABECALHO", completo),
+            unidade: var("UNIDADE", unidade),
+        }
+    }
+}
+
+// Rótulos e unidades usados nos cards e no histórico
+#[derive(Debug, Clone)]
+struct Rotulos {
+    temperatura: RotuloCampo,
+    umidade: RotuloCampo,
+    pressao: RotuloCampo,
+}
+
+impl Rotulos {
+    fn from_env() -> Self {
+        Self {
+            temperatura: RotuloCampo::from_env("TEMPERATURA", "Temp", "Temp", "°C"),
+            umidade: RotuloCampo::from_env("UMIDADE", "Umid", "Umidade", "%"),
+            pressao: RotuloCampo::from_env("PRESSAO", "Press", "Pressão", "hPa"),
+        }
+    }
+}
+
+// Modo de exibição do histórico (?view=table | ?view=list)
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Visualizacao {
+    #[default]
+    Table,
+    List,
+}
+
+impl Visualizacao {
+    fn parametro(self) -> &'static str {
+        match self {
+            Visualizacao::Table => "table",
+            Visualizacao::List => "list",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct DashboardParams {
+    #[serde(default)]
+    view: Visualizacao,
+    // Texto livre para que valores desconhecidos caiam em Celsius em vez de erro 400
+    unit: Option<String>,
+    // Idem: valor desconhecido segue o tema do sistema
+    theme: Option<String>,
+}
+
+impl DashboardParams {
+    fn unidade(&self) -> UnidadeTemperatura {
+        match self.unit.as_deref() {
+            Some("f" | "F") => UnidadeTemperatura::Fahrenheit,
+            _ => UnidadeTemperatura::Celsius,
+        }
+    }
+
+    fn tema(&self) -> Tema {
+        match self.theme.as_deref() {
+            Some("dark") => Tema::Escuro,
+            Some("light") => Tema::Claro,
+            _ => Tema::Sistema,
+        }
+    }
+}
+
+// Tema do dashboard (?theme=light | ?theme=dark); sem o parâmetro segue prefers-color-scheme
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Tema {
+    Sistema,
+    Claro,
+    Escuro,
+}
+
+impl Tema {
+    fn parametro(self) -> &'static str {
+        match self {
+            Tema::Sistema => "auto",
+            Tema::Claro => "light",
+            Tema::Escuro => "dark",
+        }
+    }
+
+    // CSS acrescentado depois do tema claro padrão
+    fn css(self) -> String {
+        match self {
+            Tema::Sistema => format!("@media (prefers-color-scheme: dark) {{{}}}", CSS_TEMA_ESCURO),
+            Tema::Claro => String::new(),
+            Tema::Escuro => CSS_TEMA_ESCURO.to_string(),
+        }
+    }
+
+    // Destino e texto do link de alternar: o oposto do tema em uso (no automático, o navegador decide qual é)
+    fn alternar(self) -> (Tema, &'static str) {
+        match self {
+            Tema::Escuro => (Tema::Claro, "☀️ Claro"),
+            _ => (Tema::Escuro, "🌙 Escuro"),
+        }
+    }
+}
+
+// Unidade de exibição da temperatura (?unit=c | ?unit=f); os dados ficam sempre em °C
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum UnidadeTemperatura {
+    Celsius,
+    Fahrenheit,
+}
+
+impl UnidadeTemperatura {
+    fn converter(self, celsius: f64) -> f64 {
+        match self {
+            UnidadeTemperatura::Celsius => celsius,
+            UnidadeTemperatura::Fahrenheit => celsius * 9.0 / 5.0 + 32.0,
+        }
+    }
+
+    fn simbolo(self) -> &'static str {
+        match self {
+            UnidadeTemperatura::Celsius => "°C",
+            UnidadeTemperatura::Fahrenheit => "°F",
+        }
+    }
+
+    fn parametro(self) -> &'static str {
+        match self {
+            UnidadeTemperatura::Celsius => "c",
+            UnidadeTemperatura::Fahrenheit => "f",
+        }
+    }
+}
+
+// ?punit=hpa | inhg | mmhg, no dashboard e nas rotas JSON de leituras.
+// Texto livre: valores desconhecidos caem em hPa em vez de erro 400.
+#[derive(Deserialize, Debug, Default)]
+struct PressaoParams {
+    punit: Option<String>,
+    // ?msl=1: dashboard e /api/stats usam a pressão reduzida ao nível do mar
+    msl: Option<String>,
+}
+
+impl PressaoParams {
+    fn nivel_mar(&self) -> bool {
+        matches!(self.msl.as_deref(), Some("1" | "true"))
+    }
+
+    fn unidade(&self) -> UnidadePressao {
+        match self.punit.as_deref().map(str::to_lowercase).as_deref() {
+            Some("inhg") => UnidadePressao::InHg,
+            Some("mmhg") => UnidadePressao::MmHg,
+            _ => UnidadePressao::Hpa,
+        }
+    }
+}
+
+// Unidade de exibição da pressão; os dados ficam sempre em hPa
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum UnidadePressao {
+    Hpa,
+    InHg,
+    MmHg,
+}
+
+impl UnidadePressao {
+    fn fator(self) -> f64 {
+        match self {
+            UnidadePressao::Hpa => 1.0,
+            UnidadePressao::InHg => HPA_PARA_INHG,
+            UnidadePressao::MmHg => HPA_PARA_MMHG,
+        }
+    }
+
+    fn converter(self, hpa: f64) -> f64 {
+        hpa * self.fator()
+    }
+
+    // inHg precisa de duas casas: 1 hPa vale só 0,03 inHg
+    fn casas(self) -> usize {
+        match self {
+            UnidadePressao::InHg => 2,
+            UnidadePressao::Hpa | UnidadePressao::MmHg => 1,
+        }
+    }
+
+    fn formatar(self, hpa: f64) -> String {
+        format!("{:.*}", self.casas(), self.converter(hpa))
+    }
+
+    fn simbolo(self) -> &'static str {
+        match self {
+            UnidadePressao::Hpa => "hPa",
+            UnidadePressao::InHg => "inHg",
+            UnidadePressao::MmHg => "mmHg",
+        }
+    }
+
+    fn parametro(self) -> &'static str {
+        match self {
+            UnidadePressao::Hpa => "hpa",
+            UnidadePressao::InHg => "inhg",
+            UnidadePressao::MmHg => "mmhg",
+        }
+    }
+}
+
+// Unidades de exibição escolhidas na página
+#[derive(Debug, Clone, Copy)]
+struct Unidades {
+    temperatura: UnidadeTemperatura,
+    pressao: UnidadePressao,
+}
+
+// Mínimo, máximo e média de uma grandeza na janela
+#[derive(Serialize, Debug)]
+struct Estatistica {
+    min: f64,
+    max: f64,
+    media: f64,
+}
+
+impl Estatistica {
+    fn calcular(valores: impl Iterator<Item = f64>) -> Option<Self> {
+        let mut min = f64::INFINITY;
+        let mut max = f64::NEG_INFINITY;
+        let mut soma = 0.0;
+        let mut n = 0usize;
+
+        for v in valores {
+            min = min.min(v);
+            max = max.max(v);
+            soma += v;
+            n += 1;
+        }
+
+        (n > 0).then(|| Self { min, max, media: soma / n as f64 })
+    }
+}
+
+// Mensagem publicada a cada janela de agregação
+#[derive(Serialize, Debug)]
+struct Agregado {
+    dispositivo: String,
+    inicio: String,
+    fim: String,
+    amostras: usize,
+    temperatura: Estatistica,
+    // None quando nenhuma leitura da janela trouxe a grandeza
+    umidade: Option<Estatistica>,
+    pressao: Option<Estatistica>,
+    // Última menos primeira pressão da janela (None com menos de duas leituras com pressão)
+    variacao_pressao: Option<f64>,
+    tendencia_pressao: Option<TendenciaPressao>,
+    // Desvio padrão da pressão na janela (None com menos de duas leituras com pressão)
+    desvio_pressao: Option<f64>,
+    confianca_tendencia: Option<ConfiancaTendencia>,
+}
+
+impl Agregado {
+    fn calcular(dispositivo: String, janela: &[SensorData], inicio: String, fim: String) -> Option<Self> {
+        let pressoes: Vec<f64> = janela.iter().filter_map(|d| d.pressao).collect();
+        let variacao_pressao = match (pressoes.first(), pressoes.last()) {
+            (Some(primeira), Some(ultima)) if pressoes.len() >= 2 => Some(ultima - primeira),
+            _ => None,
+        };
+        let desvio_pressao = desvio_padrao(&pressoes);
+
+        Some(Self {
+            dispositivo,
+            inicio,
+            fim,
+            amostras: janela.len(),
+            temperatura: Estatistica::calcular(janela.iter().map(|d| d.temperatura))?,
+            umidade: Estatistica::calcular(janela.iter().filter_map(|d| d.umidade)),
+            pressao: Estatistica::calcular(pressoes.iter().copied()),
+            variacao_pressao,
+            tendencia_pressao: variacao_pressao.map(TendenciaPressao::calcular),
+            desvio_pressao,
+            confianca_tendencia: desvio_pressao.map(ConfiancaTendencia::calcular),
+        })
+    }
+}
+
+// Mensagem publicada no tópico de derivadas após cada leitura aceita
+#[derive(Serialize, Debug)]
+struct MetricasDerivadas {
+    dispositivo: String,
+    timestamp: DateTime<Local>,
+    // None sem umidade
+    ponto_orvalho: Option<f64>,
+    sensacao_termica: Option<f64>,
+    // Sobre o histórico do dispositivo, como no dashboard (None com menos de duas pressões)
+    variacao_pressao: Option<f64>,
+    tendencia_pressao: Option<TendenciaPressao>,
+}
+
+impl MetricasDerivadas {
+    fn calcular(dispositivo: &str, history: &VecDeque<Registro>) -> Option<Self> {
+        let ultimo = history.back()?;
+        let dados = ultimo.dados;
+        let variacao_pressao = resumo_historico(dispositivo, history).and_then(|r| r.variacao_pressao);
+
+        Some(Self {
+            dispositivo: dispositivo.to_string(),
+            timestamp: ultimo.horario,
+            ponto_orvalho: dados.umidade.map(|rh| dew_point(dados.temperatura, rh)),
+            sensacao_termica: dados.umidade.map(|rh| heat_index(dados.temperatura, rh)),
+            variacao_pressao,
+            tendencia_pressao: variacao_pressao.map(TendenciaPressao::calcular),
+        })
+    }
+}
+
+// Desvio padrão amostral (n − 1); uma única amostra não tem variância
+fn desvio_padrao(valores: &[f64]) -> Option<f64> {
+    if valores.len() < 2 {
+        return None;
+    }
+
+    let n = valores.len() as f64;
+    let media = valores.iter().sum::<f64>() / n;
+    let soma_quadrados: f64 = valores.iter().map(|v| (v - media).powi(2)).sum();
+    Some((soma_quadrados / (n - 1.0)).sqrt())
+}
+
+// Previsão simples pela tendência barométrica: pressão subindo indica melhora do tempo
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum TendenciaPressao {
+    Subindo,
+    Estavel,
+    Caindo,
+}
+
+impl TendenciaPressao {
+    fn calcular(variacao: f64) -> Self {
+        if variacao >= TENDENCIA_PRESSAO_LIMIAR_HPA {
+            TendenciaPressao::Subindo
+        } else if variacao <= -TENDENCIA_PRESSAO_LIMIAR_HPA {
+            TendenciaPressao::Caindo
+        } else {
+            TendenciaPressao::Estavel
+        }
+    }
+
+    // Seta e previsão exibidas no dashboard (o script usa os mesmos textos)
+    fn rotulo(self) -> &'static str {
+        match self {
+            TendenciaPressao::Subindo => "↑ melhorando",
+            TendenciaPressao::Estavel => "→ estável",
+            TendenciaPressao::Caindo => "↓ piorando",
+        }
+    }
+}
+
+// Quanto confiar na seta da tendência: pressão oscilando muito na janela (rajadas,
+// sensor instável) torna a diferença entre a primeira e a última leitura pouco significativa
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum ConfiancaTendencia {
+    Firme,
+    Volatil,
+}
+
+impl ConfiancaTendencia {
+    fn calcular(desvio_hpa: f64) -> Self {
+        if desvio_hpa > VOLATILIDADE_PRESSAO_LIMIAR_HPA {
+            ConfiancaTendencia::Volatil
+        } else {
+            ConfiancaTendencia::Firme
+        }
+    }
+
+    // O script usa os mesmos textos
+    fn rotulo(self) -> &'static str {
+        match self {
+            ConfiancaTendencia::Firme => "firme",
+            ConfiancaTendencia::Volatil => "volátil",
+        }
+    }
+}
+
+// Veredito de conforto exibido no banner acima dos cards
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Conforto {
+    Confortavel,
+    Seco,
+    Umido,
+    Quente,
+    Frio,
+}
+
+impl Conforto {
+    const TODOS: [Conforto; 5] =
+        [Conforto::Confortavel, Conforto::Seco, Conforto::Umido, Conforto::Quente, Conforto::Frio];
+
+    // A temperatura decide primeiro; sem umidade (sensor ausente) só ela é considerada
+    fn classificar(temp_c: f64, umidade: Option<f64>) -> Self {
+        if temp_c > CONFORTO_TEMPERATURA_C.1 {
+            Conforto::Quente
+        } else if temp_c < CONFORTO_TEMPERATURA_C.0 {
+            Conforto::Frio
+        } else {
+            match umidade {
+                Some(rh) if rh < CONFORTO_UMIDADE.0 => Conforto::Seco,
+                Some(rh) if rh > CONFORTO_UMIDADE.1 => Conforto::Umido,
+                _ => Conforto::Confortavel,
+            }
+        }
+    }
+
+    // Mesmo nome da serialização; também é a classe CSS do banner
+    fn chave(self) -> &'static str {
+        match self {
+            Conforto::Confortavel => "confortavel",
+            Conforto::Seco => "seco",
+            Conforto::Umido => "umido",
+            Conforto::Quente => "quente",
+            Conforto::Frio => "frio",
+        }
+    }
+
+    // Valor de `comfort` em ?lang=en
+    fn chave_en(self) -> &'static str {
+        match self {
+            Conforto::Confortavel => "comfortable",
+            Conforto::Seco => "dry",
+            Conforto::Umido => "humid",
+            Conforto::Quente => "hot",
+            Conforto::Frio => "cold",
+        }
+    }
+
+    fn rotulo(self) -> &'static str {
+        match self {
+            Conforto::Confortavel => "😊 Confortável",
+            Conforto::Seco => "🌵 Ar seco",
+            Conforto::Umido => "💧 Úmido demais",
+            Conforto::Quente => "🔥 Quente",
+            Conforto::Frio => "❄️ Frio",
+        }
+    }
+}
+
+// Objeto JS `chave: rótulo` usado pelo script para atualizar o banner
+fn conforto_js() -> String {
+    let pares: Vec<String> = Conforto::TODOS.iter().map(|c| format!("{}: \"{}\"", c.chave(), c.rotulo())).collect();
+    format!("{{ {} }}", pares.join(", "))
+}
+
+// Texto da confiança e largura (%) da barra de volatilidade ("—" e barra vazia sem variância)
+fn formatar_confianca(desvio: Option<f64>, unidade: UnidadePressao) -> (String, f64) {
+    match desvio {
+        Some(d) => (
+            format!(
+                "{} (σ {:.*} {})",
+                ConfiancaTendencia::calcular(d).rotulo(),
+                unidade.casas(),
+                unidade.converter(d),
+                unidade.simbolo()
+            ),
+            (d / (2.0 * VOLATILIDADE_PRESSAO_LIMIAR_HPA)).min(1.0) * 100.0,
+        ),
+        None => ("—".to_string(), 0.0),
+    }
+}
+
+// Texto da tendência de pressão ("—" com menos de duas leituras)
+fn formatar_tendencia(variacao: Option<f64>, unidade: UnidadePressao) -> String {
+    match variacao {
+        Some(v) => format!(
+            "{} ({:+.*} {})",
+            TendenciaPressao::calcular(v).rotulo(),
+            unidade.casas(),
+            unidade.converter(v),
+            unidade.simbolo()
+        ),
+        None => "—".to_string(),
+    }
+}
+
+impl Agregado {
+    // Estatísticas e variação da pressão na unidade pedida com ?punit= (a tendência é decidida em hPa)
+    fn com_pressao_em(mut self, unidade: UnidadePressao) -> Self {
+        self.pressao = self.pressao.map(|e| Estatistica {
+            min: unidade.converter(e.min),
+            max: unidade.converter(e.max),
+            media: unidade.converter(e.media),
+        });
+        self.variacao_pressao = self.variacao_pressao.map(|v| unidade.converter(v));
+        self.desvio_pressao = self.desvio_pressao.map(|d| unidade.converter(d));
+        self
+    }
+}
+
+// Cópia do histórico com `pressao` reduzida ao nível do mar (?msl=1). Leituras sem
+// altitude (firmware antigo, dispositivos sem BMP280) mantêm a pressão da estação.
+fn historico_nivel_mar(history: &VecDeque<Registro>) -> Historico {
+    Arc::new(
+        history
+            .iter()
+            .map(|reg| {
+                let mut reg = reg.clone();
+                reg.dados.pressao = reg.dados.pressao_nivel_mar().or(reg.dados.pressao);
+                reg
+            })
+            .collect(),
+    )
+}
+
+// Mínimo, máximo e média de cada grandeza no histórico de um dispositivo (None se vazio)
+fn resumo_historico(dispositivo: &str, history: &VecDeque<Registro>) -> Option<Agregado> {
+    let dados: Vec<SensorData> = history.iter().map(|reg| reg.dados).collect();
+    Agregado::calcular(
+        dispositivo.to_string(),
+        &dados,
+        history.front()?.horario.format("%H:%M:%S").to_string(),
+        history.back()?.horario.format("%H:%M:%S").to_string(),
+    )
+}
+
+// Texto da linha de estatísticas (o script do dashboard usa o mesmo formato)
+fn formatar_estatistica(e: &Estatistica, converter: impl Fn(f64) -> f64, casas: usize) -> String {
+    format!(
+        "mín {:.*} · média {:.*} · máx {:.*}",
+        casas,
+        converter(e.min),
+        casas,
+        converter(e.media),
+        casas,
+        converter(e.max)
+    )
+}
+
+// Leituras acumuladas desde a última publicação de agregado, por dispositivo
+type JanelaAgregado = Arc<Mutex<HashMap<String, Vec<SensorData>>>>;
+
+// O estado é uma LISTA (Vector) de registros por dispositivo, protegida por Mutex
+//
+// Cada lista fica atrás de um `Arc` (copy-on-write): leitores só clonam os `Arc` dentro
+// do lock e montam a resposta fora dele; a escrita usa `Arc::make_mut`, que copia a
+// lista apenas se algum leitor ainda segura o snapshot anterior.
+// Medido (release, 1000 registros): clonar o Vec dentro do lock ~34 µs, renderizar a
+// tabela dentro do lock ~280 µs; clonar o Arc ~25 ns, independente do tamanho.
+type Historico = Arc<VecDeque<Registro>>;
+type Historicos = HashMap<String, Historico>;
+type SharedState = Arc<Mutex<Historicos>>;
+// Conexão SQLite usada pelo loop MQTT (gravação) e por /api/aggregate (consulta)
+type BancoCompartilhado = Arc<Mutex<Connection>>;
+
+// Extrai o id do dispositivo do tópico recebido. Com o filtro `prefixo/+` o id é o nível
+// coberto pelo `+`; com um tópico fixo, é o último nível dele. Outros tópicos não são leituras.
+fn dispositivo_do_topico<'a>(filtro: &str, topico: &'a str) -> Option<&'a str> {
+    let id = match filtro.strip_suffix('+') {
+        Some(prefixo) => topico.strip_prefix(prefixo)?,
+        None if topico == filtro => topico.rsplit('/').next()?,
+        None => return None,
+    };
+    id_dispositivo_valido(id).then_some(id)
+}
+
+// Id do dispositivo em um tópico de heartbeat (o nível no lugar do `+` de TOPICO_HEARTBEAT)
+fn dispositivo_do_heartbeat(topico: &str) -> Option<&str> {
+    let (prefixo, sufixo) = TOPICO_HEARTBEAT.split_once('+')?;
+    let id = topico.strip_prefix(prefixo)?.strip_suffix(sufixo)?;
+    id_dispositivo_valido(id).then_some(id)
+}
+
+// O id vira parte do HTML (ids de elementos) e dos rótulos do /metrics, então só caracteres seguros
+fn id_dispositivo_valido(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+// Escapa texto interpolado no HTML do dashboard
+fn escapar_html(texto: &str) -> String {
+    let mut saida = String::with_capacity(texto.len());
+    for c in texto.chars() {
+        match c {
+            '&' => saida.push_str("&amp;"),
+            '<' => saida.push_str("&lt;"),
+            '>' => saida.push_str("&gt;"),
+            '"' => saida.push_str("&quot;"),
+            '\'' => saida.push_str("&#39;"),
+            _ => saida.push(c),
+        }
+    }
+    saida
+}
+
+// Escapa o valor de um rótulo no formato de exposição do Prometheus
+fn escapar_rotulo(texto: &str) -> String {
+    texto.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+// ?device=<id> nas rotas que mostram um único dispositivo
+#[derive(Deserialize, Debug, Default)]
+struct DispositivoParams {
+    device: Option<String>,
+}
+
+impl DispositivoParams {
+    fn id(&self) -> &str {
+        self.device.as_deref().unwrap_or(DISPOSITIVO_PADRAO)
+    }
+
+    // Rotas que gravam no histórico: o id precisa passar pela mesma regra dos tópicos MQTT
+    fn id_para_gravar(&self) -> Result<&str, (StatusCode, String)> {
+        let id = self.id();
+        if !id_dispositivo_valido(id) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "device deve ter só letras, números, _ ou -".to_string(),
+            ));
+        }
+        Ok(id)
+    }
+}
+
+// Um dispositivo novo só entra no histórico abaixo de DISPOSITIVOS_MAX
+fn verificar_limite_dispositivos(historicos: &Historicos, id: &str) -> Result<(), (StatusCode, String)> {
+    if !historicos.contains_key(id) && historicos.len() >= DISPOSITIVOS_MAX {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("Limite de {} dispositivos atingido", DISPOSITIVOS_MAX),
+        ));
+    }
+    Ok(())
+}
+
+// ?since=<RFC 3339> em /api/history: só leituras posteriores (timestamp inválido responde 400)
+#[derive(Deserialize, Debug, Default)]
+struct HistoricoParams {
+    since: Option<DateTime<FixedOffset>>,
+}
+
+// Estratégia de redução de pontos para gráficos
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Decimacao {
+    // Mantém um ponto a cada K
+    Passo(usize),
+    // Divide a série em N colunas e mantém o mínimo e o máximo de cada uma
+    MinMax(usize),
+}
+
+// Reduz a série preservando a ordem original. Com MinMax os extremos visuais são mantidos.
+fn decimar<T: Clone>(pontos: &[T], modo: Decimacao, valor: impl Fn(&T) -> f64) -> Vec<T> {
+    match modo {
+        Decimacao::Passo(k) => pontos.iter().step_by(k.max(1)).cloned().collect(),
+        Decimacao::MinMax(colunas) => {
+            if colunas == 0 || pontos.len() <= colunas * 2 {
+                return pontos.to_vec();
+            }
+
+            let tamanho = pontos.len().div_ceil(colunas);
+            let mut saida = Vec::with_capacity(colunas * 2);
+
+            for bloco in pontos.chunks(tamanho) {
+                let (mut i_min, mut i_max) = (0, 0);
+                for (i, p) in bloco.iter().enumerate() {
+                    if valor(p) < valor(&bloco[i_min]) {
+                        i_min = i;
+                    }
+                    if valor(p) > valor(&bloco[i_max]) {
+                        i_max = i;
+                    }
+                }
+
+                let (primeiro, segundo) = if i_min <= i_max { (i_min, i_max) } else { (i_max, i_min) };
+                saida.push(bloco[primeiro].clone());
+                if segundo != primeiro {
+                    saida.push(bloco[segundo].clone());
+                }
+            }
+
+            saida
+        }
+    }
+}
+
+// Grandeza selecionada em /api/series
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+enum Campo {
+    #[default]
+    Temperatura,
+    Umidade,
+    Pressao,
+}
+
+impl Campo {
+    fn valor(&self, dados: &SensorData) -> Option<f64> {
+        match self {
+            Campo::Temperatura => Some(dados.temperatura),
+            Campo::Umidade => dados.umidade,
+            Campo::Pressao => dados.pressao,
+        }
+    }
+
+    // Coluna correspondente na tabela readings
+    fn coluna(&self) -> &'static str {
+        match self {
+            Campo::Temperatura => "temperatura",
+            Campo::Umidade => "umidade",
+            Campo::Pressao => "pressao",
+        }
+    }
+}
+
+// Tamanho do intervalo em /api/aggregate
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+enum Intervalo {
+    #[default]
+    Hour,
+    Day,
+}
+
+impl Intervalo {
+    // Prefixo do timestamp RFC 3339 gravado que identifica o intervalo ("2026-10-16T13" ou "2026-10-16").
+    // O horário é o local do servidor na hora da gravação.
+    fn tamanho_prefixo(self) -> usize {
+        match self {
+            Intervalo::Hour => 13,
+            Intervalo::Day => 10,
+        }
+    }
+}
+
+// ?bucket=hour|day&metric=temperatura|umidade|pressao
+#[derive(Deserialize, Debug, Default)]
+struct AgregacaoParams {
+    #[serde(default)]
+    bucket: Intervalo,
+    #[serde(default)]
+    metric: Campo,
+}
+
+// Um intervalo de /api/aggregate
+#[derive(Serialize, Debug)]
+struct IntervaloAgregado {
+    // Início do intervalo: "2026-10-16T13:00" (hour) ou "2026-10-16" (day)
+    inicio: String,
+    amostras: i64,
+    min: f64,
+    max: f64,
+    media: f64,
+}
+
+// ?campo=pressao em /api/series
+#[derive(Deserialize, Debug, Default)]
+struct SeriesParams {
+    #[serde(default)]
+    campo: Campo,
+}
+
+// ?passo=3 ou ?colunas=100 (colunas tem prioridade) em /api/series e /chart.png
+#[derive(Deserialize, Debug, Default)]
+struct DecimacaoParams {
+    passo: Option<usize>,
+    colunas: Option<usize>,
+}
+
+impl DecimacaoParams {
+    fn decimacao(&self) -> Option<Decimacao> {
+        self.colunas
+            .map(Decimacao::MinMax)
+            .or(self.passo.map(Decimacao::Passo))
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct PontoSerie {
+    horario: String,
+    valor: f64,
+}
+
+// Resultado do POST /api/import
+#[derive(Serialize, Debug, Default)]
+struct ResultadoImportacao {
+    importados: usize,
+    ignorados: usize,
+    // Registros mais antigos descartados para respeitar a capacidade (ou a idade máxima) do histórico
+    descartados_por_capacidade: usize,
+}
+
+// Arquivo do SPIFFS enviado em POST /api/ingest (?sensor=)
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum FonteIngestao {
+    // bmp280_data.txt: `timestamp,temperatura,pressao,altitude`
+    Bmp280,
+    // dht11_data.txt: `timestamp,temperatura,umidade`
+    Dht11,
+}
+
+impl FonteIngestao {
+    // Grandeza que só esta fonte mede, usada para reconhecer duplicatas
+    fn campo(self) -> Campo {
+        match self {
+            FonteIngestao::Bmp280 => Campo::Pressao,
+            FonteIngestao::Dht11 => Campo::Umidade,
+        }
+    }
+
+    // Linha no formato de `gravar_bmp280`/`gravar_dht11` (timestamp em época Unix, segundos)
+    fn parse_linha(self, linha: &str) -> Option<Registro> {
+        let campos: Vec<&str> = linha.split(',').map(str::trim).collect();
+        let (timestamp, temperatura) = (campos.first()?, campos.get(1)?);
+
+        let timestamp: i64 = timestamp.parse().ok()?;
+        if timestamp < EPOCH_MINIMO_VALIDO {
+            return None;
+        }
+        let horario = DateTime::from_timestamp(timestamp, 0)?.with_timezone(&Local);
+
+        let mut dados = SensorData {
+            temperatura: temperatura.parse().ok()?,
+            umidade: None,
+            pressao: None,
+            temperatura_dht11: None,
+            bateria: None,
+            altitude: None,
+        };
+        match (self, &campos[2..]) {
+            (FonteIngestao::Bmp280, [pressao, altitude]) => {
+                dados.pressao = Some(pressao.parse().ok()?);
+                dados.altitude = Some(altitude.parse().ok()?);
+            }
+            (FonteIngestao::Dht11, [umidade]) => dados.umidade = Some(umidade.parse().ok()?),
+            _ => return None,
+        }
+
+        Some(Registro { dados, horario })
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct IngestaoParams {
+    sensor: FonteIngestao,
+}
+
+// Resultado do POST /api/ingest
+#[derive(Serialize, Debug, Default)]
+struct ResultadoIngestao {
+    importados: usize,
+    // Já presentes no histórico ou no banco (reenvio do mesmo arquivo)
+    duplicados: usize,
+    // Linhas malformadas, fora das faixas ou com timestamp de antes da sincronização do relógio
+    ignorados: usize,
+    descartados_por_capacidade: usize,
+}
+
+// Converte uma linha `horario,temperatura,umidade,pressao` em Registro (umidade e pressao podem ficar vazias)
+fn parse_linha_csv(linha: &str) -> Option<Registro> {
+    let campos: Vec<&str> = linha.split(',').map(str::trim).collect();
+    let [horario, temperatura, umidade, pressao] = campos[..] else {
+        return None;
+    };
+
+    let horario = parse_horario(horario)?;
+
+    Some(Registro {
+        dados: SensorData {
+            temperatura: temperatura.parse().ok()?,
+            umidade: parse_opcional(umidade)?,
+            pressao: parse_opcional(pressao)?,
+            temperatura_dht11: None,
+            bateria: None,
+            altitude: None,
+        },
+        horario,
+    })
+}
+
+// Campo vazio é Some(None); texto que não é número invalida a linha (None)
+fn parse_opcional(campo: &str) -> Option<Option<f64>> {
+    if campo.is_empty() {
+        return Some(None);
+    }
+    campo.parse().ok().map(Some)
+}
+
+// Umidade absoluta (g/m³): pressão de vapor de saturação (Magnus) × umidade relativa.
+// A temperatura é limitada à faixa de validade de Magnus; perto de -237,7 °C a fórmula explodiria.
+fn absolute_humidity(temp_c: f64, rh: f64) -> f64 {
+    let temp_c = temp_c.clamp(MAGNUS_FAIXA_C.0, MAGNUS_FAIXA_C.1);
+    let rh = rh.clamp(0.0, 100.0);
+    let saturacao = MAGNUS_ES0_HPA * (MAGNUS_A * temp_c / (MAGNUS_B + temp_c)).exp();
+    UMIDADE_ABSOLUTA_K * saturacao * rh / 100.0 / (temp_c + 273.15)
+}
+
+// Ponto de orvalho (°C) pela fórmula de Magnus.
+// A umidade é limitada a (0, 100] para que RH=0 não vire ln(0) = -inf.
+fn dew_point(temp_c: f64, rh: f64) -> f64 {
+    let rh = rh.clamp(0.1, 100.0);
+    let gama = (rh / 100.0).ln() + MAGNUS_A * temp_c / (MAGNUS_B + temp_c);
+    MAGNUS_B * gama / (MAGNUS_A - gama)
+}
+
+// Pressão ao nível do mar (hPa) a partir da pressão da estação, pela fórmula barométrica
+// com a temperatura medida: P0 = P · (1 − L·h / (T + L·h + 273,15))^−5,257.
+// Com altitude negativa (estação abaixo da referência do firmware) a correção se inverte:
+// a pressão ao nível do mar fica menor que a medida.
+fn to_sea_level(pressure_hpa: f64, altitude_m: f64, temp_c: f64) -> f64 {
+    let queda = GRADIENTE_TERMICO_K_POR_M * altitude_m;
+    pressure_hpa * (1.0 - queda / (temp_c + queda + 273.15)).powf(-EXPOENTE_BAROMETRICO)
+}
+
+// Sensação térmica (°C) pela regressão de Rothfusz (NOAA), calculada em °F
+fn heat_index(temp_c: f64, rh: f64) -> f64 {
+    if temp_c < HEAT_INDEX_MIN_C {
+        return temp_c;
+    }
+
+    let t = temp_c * 9.0 / 5.0 + 32.0;
+    let hi = -42.379 + 2.04901523 * t + 10.14333127 * rh
+        - 0.22475541 * t * rh
+        - 0.00683783 * t * t
+        - 0.05481717 * rh * rh
+        + 0.00122874 * t * t * rh
+        + 0.00085282 * t * rh * rh
+        - 0.00000199 * t * t * rh * rh;
+
+    (hi - 32.0) * 5.0 / 9.0
+}
+
+// Comparação entre as duas fontes de temperatura
+#[derive(Serialize, Debug, Clone, Copy)]
+struct Divergencia {
+    temperatura_bmp280: f64,
+    temperatura_dht11: f64,
+    delta: f64,
+    limite: f64,
+    alerta: bool,
+}
+
+impl Divergencia {
+    fn calcular(dados: &SensorData) -> Option<Self> {
+        let dht11 = dados.temperatura_dht11?;
+        let delta = (dados.temperatura - dht11).abs();
+
+        Some(Self {
+            temperatura_bmp280: dados.temperatura,
+            temperatura_dht11: dht11,
+            delta,
+            limite: DIVERGENCIA_TEMPERATURA_MAX_C,
+            alerta: delta > DIVERGENCIA_TEMPERATURA_MAX_C,
+        })
+    }
+}
+
+// ?pretty=1 nas rotas JSON para saída indentada
+#[derive(Deserialize, Debug, Default)]
+struct FormatoParams {
+    pretty: Option<String>,
+}
+
+impl FormatoParams {
+    fn pretty(&self) -> bool {
+        matches!(self.pretty.as_deref(), Some("1" | "true"))
+    }
+}
+
+// Resposta JSON compacta por padrão ou indentada sob demanda
+struct JsonFormatado<T>(T, bool);
+
+impl<T: Serialize> IntoResponse for JsonFormatado<T> {
+    fn into_response(self) -> Response {
+        let JsonFormatado(valor, pretty) = self;
+        let corpo = if pretty {
+            serde_json::to_string_pretty(&valor)
+        } else {
+            serde_json::to_string(&valor)
+        };
+
+        match corpo {
+            Ok(corpo) => ([(header::CONTENT_TYPE, "application/json")], corpo).into_response(),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        }
+    }
+}
+
+// Intervalo aceito para uma grandeza (limites inclusivos)
+#[derive(Serialize, Debug, Clone, Copy)]
+struct Faixa {
+    min: f64,
+    max: f64,
+}
+
+impl From<(f64, f64)> for Faixa {
+    fn from((min, max): (f64, f64)) -> Self {
+        Self { min, max }
+    }
+}
+
+impl Faixa {
+    fn contem(&self, valor: f64) -> bool {
+        (self.min..=self.max).contains(&valor)
+    }
+
+    // Lê "min,max" da variável de ambiente, mantendo o padrão se ausente ou inválida
+    fn from_env(var: &str, padrao: Faixa) -> Faixa {
+        let Ok(texto) = std::env::var(var) else {
+            return padrao;
+        };
+
+        let faixa = texto.split_once(',').and_then(|(min, max)| {
+            Some(Faixa {
+                min: min.trim().parse().ok()?,
+                max: max.trim().parse().ok()?,
+            })
+        });
+
+        match faixa {
+            Some(f) if f.min <= f.max => f,
+            _ => {
+                warn!("{} inválida ({:?}), usando {:?}", var, texto, padrao);
+                padrao
+            }
+        }
+    }
+}
+
+// Lê HISTORY_CAP, mantendo o padrão se ausente, inválida ou zero
+fn capacidade_historico_from_env() -> usize {
+    let Ok(texto) = std::env::var(ENV_HISTORY_CAP) else {
+        return HISTORICO_MAX_PADRAO;
+    };
+
+    match texto.trim().parse() {
+        Ok(n) if n > 0 => n,
+        _ => {
+            warn!("{} inválida ({:?}), usando {}", ENV_HISTORY_CAP, texto, HISTORICO_MAX_PADRAO);
+            HISTORICO_MAX_PADRAO
+        }
+    }
+}
+
+// Lê HISTORY_MAX_AGE_MINUTES; None (só a retenção por quantidade) se ausente, inválida ou zero
+fn idade_maxima_historico_from_env() -> Option<TimeDelta> {
+    let texto = std::env::var(ENV_HISTORY_MAX_AGE).ok()?;
+
+    match texto.trim().parse() {
+        Ok(minutos) if minutos > 0 => Some(TimeDelta::minutes(minutos)),
+        _ => {
+            warn!("{} inválida ({:?}), retenção por tempo desligada", ENV_HISTORY_MAX_AGE, texto);
+            None
+        }
+    }
+}
+
+// Período da publicação de agregados; None (padrão) desliga a task
+fn intervalo_agregado_from_env() -> Option<Duration> {
+    let texto = std::env::var(ENV_AGGREGATE_INTERVAL_MINUTES).ok()?;
+
+    match texto.trim().parse::<u64>() {
+        Ok(minutos) if minutos > 0 => Some(Duration::from_secs(minutos * 60)),
+        _ => {
+            warn!("{} inválida ({:?}), agregados desligados", ENV_AGGREGATE_INTERVAL_MINUTES, texto);
+            None
+        }
+    }
+}
+
+// Descarta do início do histórico (ordem cronológica) o que passou da idade máxima e
+// o que excede a capacidade. Devolve quantos registros saíram.
+fn aplicar_retencao(
+    history: &mut VecDeque<Registro>,
+    capacidade: usize,
+    idade_maxima: Option<TimeDelta>,
+    agora: DateTime<Local>,
+) -> usize {
+    let antigos = idade_maxima.map_or(0, |idade| {
+        history.iter().take_while(|reg| agora - reg.horario > idade).count()
+    });
+    let excedente = antigos.max(history.len().saturating_sub(capacidade));
+    history.drain(..excedente);
+    excedente
+}
+
+// Lê OFFLINE_APOS_SEGUNDOS, mantendo o padrão se ausente, inválida ou não positiva
+fn offline_apos_from_env() -> i64 {
+    let Ok(texto) = std::env::var(ENV_OFFLINE_APOS_SEGUNDOS) else {
+        return OFFLINE_APOS_SEGUNDOS_PADRAO;
+    };
+
+    match texto.trim().parse() {
+        Ok(n) if n > 0 => n,
+        _ => {
+            warn!(
+                "{} inválida ({:?}), usando {}",
+                ENV_OFFLINE_APOS_SEGUNDOS, texto, OFFLINE_APOS_SEGUNDOS_PADRAO
+            );
+            OFFLINE_APOS_SEGUNDOS_PADRAO
+        }
+    }
+}
+
+// Lê e converte a variável de ambiente, mantendo o padrão se ausente ou inválida
+fn env_ou_padrao<T: std::str::FromStr + std::fmt::Debug>(var: &str, padrao: T) -> T {
+    let Ok(texto) = std::env::var(var) else {
+        return padrao;
+    };
+
+    texto.trim().parse().unwrap_or_else(|_| {
+        warn!("{} inválida ({:?}), usando {:?}", var, texto, padrao);
+        padrao
+    })
+}
+
+// Endereço do broker, transporte e tópico das leituras
+#[derive(Debug)]
+struct ConfigMqtt {
+    host: String,
+    porta: u16,
+    tls: bool,
+    // Filtros das leituras, cada um `prefixo/+` (um dispositivo por tópico) ou um tópico fixo
+    topicos: Vec<String>,
+    topico_status: String,
+    topico_derivadas: String,
+    qos: QoS,
+}
+
+impl ConfigMqtt {
+    // Sem MQTT_PORT, a porta padrão depende de MQTT_TLS (1883 ou 8883)
+    fn from_env() -> Self {
+        let tls = matches!(std::env::var(ENV_MQTT_TLS).as_deref(), Ok("1" | "true"));
+        let porta_padrao = if tls { MQTT_PORTA_TLS_PADRAO } else { MQTT_PORTA_PADRAO };
+
+        Self {
+            host: std::env::var(ENV_MQTT_HOST).unwrap_or_else(|_| MQTT_HOST_PADRAO.to_string()),
+            porta: env_ou_padrao(ENV_MQTT_PORT, porta_padrao),
+            tls,
+            topicos: topicos_leituras(&std::env::var(ENV_MQTT_TOPIC).unwrap_or_default()),
+            topico_status: std::env::var(ENV_MQTT_STATUS_TOPIC).unwrap_or_else(|_| TOPICO_STATUS_PADRAO.to_string()),
+            topico_derivadas: std::env::var(ENV_MQTT_DERIVED_TOPIC).unwrap_or_else(|_| TOPICO_DERIVADAS_PADRAO.to_string()),
+            qos: rumqttc::qos(env_ou_padrao(ENV_MQTT_QOS, MQTT_QOS_PADRAO)).unwrap_or_else(|_| {
+                warn!("{} deve ser 0, 1 ou 2; usando {}", ENV_MQTT_QOS, MQTT_QOS_PADRAO);
+                rumqttc::qos(MQTT_QOS_PADRAO).unwrap()
+            }),
+        }
+    }
+}
+
+// MQTT_TOPIC separado por vírgulas ("home/attic, home/garage"); vazio cai no padrão
+fn topicos_leituras(texto: &str) -> Vec<String> {
+    let topicos: Vec<String> = texto
+        .split(',')
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .map(str::to_string)
+        .collect();
+    if topicos.is_empty() {
+        vec![TOPICO_LEITURAS_PADRAO.to_string()]
+    } else {
+        topicos
+    }
+}
+
+// Abre (ou cria) o banco e a tabela de leituras
+fn abrir_banco(caminho: &str) -> rusqlite::Result<Connection> {
+    let conn = Connection::open(caminho)?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS readings (
+            ts TEXT NOT NULL,
+            temperatura REAL NOT NULL,
+            umidade REAL,
+            pressao REAL,
+            temperatura_dht11 REAL,
+            dispositivo TEXT,
+            bateria REAL,
+            altitude REAL
+        )",
+        (),
+    )?;
+
+    // Bancos criados antes do suporte a vários dispositivos não têm a coluna
+    if !coluna_existe(&conn, "dispositivo")? {
+        conn.execute("ALTER TABLE readings ADD COLUMN dispositivo TEXT", ())?;
+    }
+
+    // Bancos antigos exigiam umidade e pressao; o SQLite não remove NOT NULL com ALTER,
+    // então a tabela é recriada com as colunas anuláveis
+    let umidade_obrigatoria: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('readings') WHERE name IN ('umidade', 'pressao') AND \"notnull\" = 1",
+        (),
+        |row| row.get(0),
+    )?;
+    if umidade_obrigatoria {
+        conn.execute_batch(
+            "BEGIN;
+             CREATE TABLE readings_nova (
+                 ts TEXT NOT NULL,
+                 temperatura REAL NOT NULL,
+                 umidade REAL,
+                 pressao REAL,
+                 temperatura_dht11 REAL,
+                 dispositivo TEXT
+             );
+             INSERT INTO readings_nova (ts, temperatura, umidade, pressao, temperatura_dht11, dispositivo)
+                 SELECT ts, temperatura, umidade, pressao, temperatura_dht11, dispositivo FROM readings ORDER BY rowid;
+             DROP TABLE readings;
+             ALTER TABLE readings_nova RENAME TO readings;
+             COMMIT;",
+        )?;
+        info!("Banco migrado: umidade e pressao agora são opcionais");
+    }
+
+    if !coluna_existe(&conn, "bateria")? {
+        conn.execute("ALTER TABLE readings ADD COLUMN bateria REAL", ())?;
+    }
+
+    if !coluna_existe(&conn, "altitude")? {
+        conn.execute("ALTER TABLE readings ADD COLUMN altitude REAL", ())?;
+    }
+
+    Ok(conn)
+}
+
+// Se a tabela `readings` já tem a coluna, para as migrações de abrir_banco
+fn coluna_existe(conn: &Connection, nome: &str) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('readings') WHERE name = ?1",
+        [nome],
+        |row| row.get(0),
+    )
+}
+
+fn persistir_leitura(
+    conn: &Connection,
+    dispositivo: &str,
+    dados: &SensorData,
+    ts: &DateTime<Local>,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO readings (ts, temperatura, umidade, pressao, temperatura_dht11, dispositivo, bateria, altitude)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        (
+            ts.to_rfc3339(),
+            dados.temperatura,
+            dados.umidade,
+            dados.pressao,
+            dados.temperatura_dht11,
+            dispositivo,
+            dados.bateria,
+            dados.altitude,
+        ),
+    )?;
+    Ok(())
+}
+
+// Média, mínimo e máximo de uma grandeza por intervalo, em ordem cronológica.
+// Leituras sem a grandeza (null) não contam.
+fn agregar_leituras(
+    conn: &Connection,
+    dispositivo: &str,
+    campo: Campo,
+    intervalo: Intervalo,
+) -> rusqlite::Result<Vec<IntervaloAgregado>> {
+    // `coluna()` é fixa por variante, então interpolar no SQL é seguro
+    let coluna = campo.coluna();
+    let mut stmt = conn.prepare(&format!(
+        "SELECT substr(ts, 1, ?1) AS inicio, COUNT({coluna}), MIN({coluna}), MAX({coluna}), AVG({coluna})
+         FROM readings
+         WHERE COALESCE(dispositivo, ?2) = ?3 AND {coluna} IS NOT NULL
+         GROUP BY inicio ORDER BY inicio"
+    ))?;
+
+    let sufixo = match intervalo {
+        Intervalo::Hour => ":00",
+        Intervalo::Day => "",
+    };
+    let linhas = stmt.query_map((intervalo.tamanho_prefixo() as i64, DISPOSITIVO_PADRAO, dispositivo), |row| {
+        Ok(IntervaloAgregado {
+            inicio: row.get::<_, String>(0)? + sufixo,
+            amostras: row.get(1)?,
+            min: row.get(2)?,
+            max: row.get(3)?,
+            media: row.get(4)?,
+        })
+    })?;
+    linhas.collect()
+}
+
+// Últimas `limite` leituras de cada dispositivo, em ordem cronológica. Ordena por `ts`, não
+// pela ordem de inserção: o /api/ingest grava leituras antigas depois das novas.
+// Linhas sem dispositivo (bancos antigos) pertencem a DISPOSITIVO_PADRAO.
+fn carregar_recentes(conn: &Connection, limite: usize) -> rusqlite::Result<HashMap<String, VecDeque<Registro>>> {
+    let mut stmt = conn.prepare(
+        "SELECT ts, temperatura, umidade, pressao, temperatura_dht11, disp, bateria, altitude FROM (
+             SELECT *, rowid AS id, COALESCE(dispositivo, ?2) AS disp,
+                    ROW_NUMBER() OVER (PARTITION BY COALESCE(dispositivo, ?2) ORDER BY ts DESC, rowid DESC) AS n
+             FROM readings
+         ) WHERE n <= ?1 ORDER BY ts, id",
+    )?;
+
+    let linhas = stmt.query_map((limite as i64, DISPOSITIVO_PADRAO), |row| {
+        let ts: String = row.get(0)?;
+        // Linhas com timestamp ilegível são ignoradas
+        let Ok(horario) = DateTime::parse_from_rfc3339(&ts) else {
+            return Ok(None);
+        };
+
+        let registro = Registro {
+            dados: SensorData {
+                temperatura: row.get(1)?,
+                umidade: row.get(2)?,
+                pressao: row.get(3)?,
+                temperatura_dht11: row.get(4)?,
+                bateria: row.get(6)?,
+                altitude: row.get(7)?,
+            },
+            horario: horario.with_timezone(&Local),
+        };
+        Ok(Some((row.get::<_, String>(5)?, registro)))
+    })?;
+
+    let mut historicos: HashMap<String, VecDeque<Registro>> = HashMap::new();
+    for linha in linhas {
+        let Some((dispositivo, registro)) = linha? else {
+            continue;
+        };
+        historicos.entry(dispositivo).or_default().push_back(registro);
+    }
+    Ok(historicos)
+}
+
+// Faixas de sanidade aplicadas na ingestão (MQTT e importação CSV)
+#[derive(Serialize, Debug, Clone, Copy)]
+struct FaixasValidacao {
+    temperatura: Faixa,
+    umidade: Faixa,
+    pressao: Faixa,
+}
+
+impl FaixasValidacao {
+    // FAIXA_TEMPERATURA, FAIXA_UMIDADE e FAIXA_PRESSAO no formato "min,max"
+    fn from_env() -> Self {
+        Self {
+            temperatura: Faixa::from_env("FAIXA_TEMPERATURA", FAIXA_TEMPERATURA_PADRAO.into()),
+            umidade: Faixa::from_env("FAIXA_UMIDADE", FAIXA_UMIDADE_PADRAO.into()),
+            pressao: Faixa::from_env("FAIXA_PRESSAO", FAIXA_PRESSAO_PADRAO.into()),
+        }
+    }
+
+    fn validar(&self, dados: &SensorData) -> Result<(), String> {
+        // Grandezas ausentes não são validadas
+        let campos = [
+            ("temperatura", Some(dados.temperatura), self.temperatura),
+            ("umidade", dados.umidade, self.umidade),
+            ("pressao", dados.pressao, self.pressao),
+        ];
+
+        for (nome, valor, faixa) in campos {
+            let Some(valor) = valor else {
+                continue;
+            };
+            if !faixa.contem(valor) {
+                return Err(format!("{} = {} fora de [{}, {}]", nome, valor, faixa.min, faixa.max));
+            }
+        }
+
+        if let Some(dht11) = dados.temperatura_dht11 {
+            if !self.temperatura.contem(dht11) {
+                return Err(format!("temperatura_dht11 = {} fora da faixa", dht11));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Faixas que decidem a cor de um card (sempre em °C / %, qualquer que seja a unidade exibida)
+#[derive(Serialize, Debug, Clone, Copy)]
+struct LimiaresCor {
+    atencao: Faixa,
+    critico: Faixa,
+}
+
+impl LimiaresCor {
+    // COR_<GRANDEZA>_ATENCAO e COR_<GRANDEZA>_CRITICO no formato "min,max"
+    fn from_env(grandeza: &str, atencao: (f64, f64), critico: (f64, f64)) -> Self {
+        Self {
+            atencao: Faixa::from_env(&format!("COR_{}_ATENCAO", grandeza), atencao.into()),
+            critico: Faixa::from_env(&format!("COR_{}_CRITICO", grandeza), critico.into()),
+        }
+    }
+
+    fn nivel(&self, valor: f64) -> NivelCor {
+        if !self.critico.contem(valor) {
+            NivelCor::Critico
+        } else if !self.atencao.contem(valor) {
+            NivelCor::Atencao
+        } else {
+            NivelCor::Normal
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy)]
+struct CoresCards {
+    temperatura: LimiaresCor,
+    umidade: LimiaresCor,
+}
+
+impl CoresCards {
+    fn from_env() -> Self {
+        Self {
+            temperatura: LimiaresCor::from_env("TEMPERATURA", COR_TEMPERATURA_ATENCAO_PADRAO, COR_TEMPERATURA_CRITICO_PADRAO),
+            umidade: LimiaresCor::from_env("UMIDADE", COR_UMIDADE_ATENCAO_PADRAO, COR_UMIDADE_CRITICO_PADRAO),
+        }
+    }
+}
+
+// Verde / âmbar / vermelho no valor do card
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum NivelCor {
+    Normal,
+    Atencao,
+    Critico,
+}
+
+impl NivelCor {
+    // Classe CSS do card (o script usa os mesmos nomes)
+    fn classe(self) -> &'static str {
+        match self {
+            NivelCor::Normal => "nivel-normal",
+            NivelCor::Atencao => "nivel-atencao",
+            NivelCor::Critico => "nivel-critico",
+        }
+    }
+}
+
+// Classe do card para um valor opcional; sem leitura o card fica com a cor padrão
+fn classe_nivel(limiares: &LimiaresCor, valor: Option<f64>) -> &'static str {
+    valor.map_or("", |v| limiares.nivel(v).classe())
+}
+
+// Lado do limite em que a grandeza está
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+enum EstadoAlerta {
+    #[default]
+    Normal,
+    Abaixo,
+    Acima,
+}
+
+// Limites de alerta de uma grandeza; sem `min` ou `max` aquele lado não alerta
+#[derive(Debug, Clone, Copy)]
+struct LimitesAlerta {
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+impl LimitesAlerta {
+    // ALERTA_<CAMPO>_MIN e ALERTA_<CAMPO>_MAX; valores inválidos são ignorados com aviso
+    fn from_env(campo: &str) -> Self {
+        let limite = |lado: &str| {
+            let var = format!("ALERTA_{}_{}", campo, lado);
+            let texto = std::env::var(&var).ok()?;
+            let valor = texto.trim().parse().ok();
+            if valor.is_none() {
+                warn!("{} inválida ({:?}), ignorada", var, texto);
+            }
+            valor
+        };
+        Self { min: limite("MIN"), max: limite("MAX") }
+    }
+
+    fn estado(&self, valor: f64) -> EstadoAlerta {
+        if self.max.is_some_and(|max| valor > max) {
+            EstadoAlerta::Acima
+        } else if self.min.is_some_and(|min| valor < min) {
+            EstadoAlerta::Abaixo
+        } else {
+            EstadoAlerta::Normal
+        }
+    }
+
+    // Limite cruzado no estado, ou None ao voltar ao normal
+    fn limite(&self, estado: EstadoAlerta) -> Option<f64> {
+        match estado {
+            EstadoAlerta::Normal => None,
+            EstadoAlerta::Abaixo => self.min,
+            EstadoAlerta::Acima => self.max,
+        }
+    }
+}
+
+// Corpo JSON enviado ao webhook
+#[derive(Serialize, Debug)]
+struct Alerta {
+    dispositivo: String,
+    grandeza: &'static str,
+    // `normal` avisa que a grandeza voltou para dentro dos limites
+    estado: EstadoAlerta,
+    valor: f64,
+    limite: Option<f64>,
+    timestamp: DateTime<Local>,
+}
+
+// Verificação dos limites a cada leitura aceita, dentro do loop MQTT
+struct Alertas {
+    webhook: String,
+    cliente: reqwest::Client,
+    temperatura: LimitesAlerta,
+    umidade: LimitesAlerta,
+    // Último estado de cada (dispositivo, grandeza): alerta só na transição
+    estados: HashMap<(String, &'static str), EstadoAlerta>,
+}
+
+impl Alertas {
+    // None sem ALERTA_WEBHOOK_URL
+    fn from_env() -> Option<Self> {
+        let webhook = std::env::var(ENV_ALERTA_WEBHOOK_URL).ok()?;
+        let cliente = match reqwest::Client::builder().timeout(ALERTA_TIMEOUT).build() {
+            Ok(c) => c,
+            Err(e) => {
+                error!("Erro ao criar cliente HTTP dos alertas: {} (alertas desativados)", e);
+                return None;
+            }
+        };
+
+        Some(Self {
+            webhook,
+            cliente,
+            temperatura: LimitesAlerta::from_env("TEMPERATURA"),
+            umidade: LimitesAlerta::from_env("UMIDADE"),
+            estados: HashMap::new(),
+        })
+    }
+
+    // Alertas das grandezas que mudaram de estado com esta leitura
+    fn verificar(&mut self, dispositivo: &str, registro: &Registro) -> Vec<Alerta> {
+        let grandezas = [
+            ("temperatura", Some(registro.dados.temperatura), self.temperatura),
+            ("umidade", registro.dados.umidade, self.umidade),
+        ];
+
+        let mut alertas = Vec::new();
+        for (grandeza, valor, limites) in grandezas {
+            let Some(valor) = valor else {
+                continue;
+            };
+
+            let estado = limites.estado(valor);
+            let anterior = self.estados.insert((dispositivo.to_string(), grandeza), estado).unwrap_or_default();
+            if estado != anterior {
+                alertas.push(Alerta {
+                    dispositivo: dispositivo.to_string(),
+                    grandeza,
+                    estado,
+                    valor,
+                    limite: limites.limite(estado),
+                    timestamp: registro.horario,
+                });
+            }
+        }
+        alertas
+    }
+}
+
+// POST do alerta; falhas só são registradas (o próximo alerta tenta de novo)
+async fn enviar_alerta(cliente: reqwest::Client, webhook: String, alerta: Alerta) {
+    let resposta = cliente.post(&webhook).json(&alerta).send().await.and_then(|r| r.error_for_status());
+    if let Err(e) = resposta {
+        warn!("Erro ao enviar alerta para o webhook: {}", e);
+    }
+}
+
+// Configuração efetiva exposta em /api/config
+#[derive(Serialize, Debug)]
+struct ConfigPublica {
+    faixas: FaixasValidacao,
+    capacidade_historico: usize,
+    leituras_descartadas: u64,
+}
+
+// Payload publicado pelo firmware em TOPICO_HEARTBEAT
+#[derive(Deserialize, Debug, Clone, Copy)]
+struct Heartbeat {
+    uptime_s: u64,
+    erros_bmp280: u32,
+    erros_dht11: u32,
+    // Firmwares antigos não enviam os contadores por tentativa
+    #[serde(default)]
+    leituras_bmp280: Option<ContadoresLeitura>,
+    #[serde(default)]
+    leituras_umidade: Option<ContadoresLeitura>,
+    // Comparação feita no firmware só com leituras recentes dos dois sensores;
+    // ausente até haver uma. A tolerância é a do firmware.
+    #[serde(default)]
+    divergencia_temperatura_c: Option<f64>,
+    #[serde(default)]
+    sensores_divergentes: bool,
+}
+
+// Tentativas de leitura de um sensor desde o boot do firmware
+#[derive(Deserialize, Debug, Clone, Copy)]
+struct ContadoresLeitura {
+    ok: u32,
+    // Checksum/CRC inválido: sinal ruidoso ou sensor com defeito
+    crc: u32,
+    outros: u32,
+}
+
+impl ContadoresLeitura {
+    // "98.5% (3 CRC)"; "—" antes da primeira tentativa
+    fn resumo(&self) -> String {
+        let total = self.ok + self.crc + self.outros;
+        if total == 0 {
+            return "—".to_string();
+        }
+        let taxa = 100.0 * self.ok as f64 / total as f64;
+        if self.crc > 0 {
+            format!("{:.1}% ({} CRC)", taxa, self.crc)
+        } else {
+            format!("{:.1}%", taxa)
+        }
+    }
+}
+
+// Último heartbeat recebido de cada dispositivo e o instante de chegada
+type UltimoHeartbeat = Arc<Mutex<HashMap<String, (Heartbeat, DateTime<Local>)>>>;
+
+fn schema_v1() -> u32 {
+    1
+}
+
+// Histórico versionado de /api/snapshot (GET exporta, POST restaura)
+#[derive(Deserialize, Serialize, Debug)]
+struct Snapshot {
+    // Snapshots anteriores ao versionamento não têm o campo e são tratados como v1
+    #[serde(default = "schema_v1")]
+    schema_version: u32,
+    historico: Vec<Registro>,
+}
+
+impl Snapshot {
+    // Converte um snapshot de versão anterior para SCHEMA_VERSION; versão desconhecida é 422
+    fn migrar(mut self) -> Result<Self, (StatusCode, String)> {
+        match self.schema_version {
+            // v1 -> v2: temperatura_dht11 ausente já vira None pelo #[serde(default)]
+            // v2 -> v3: horario "%H:%M:%S" é lido como hoje por `deserializar_horario`
+            // v3 -> v4: umidade/pressao sempre presentes viram Some
+            // v4 -> v5: bateria ausente vira None pelo #[serde(default)]
+            // v5 -> v6: altitude ausente vira None pelo #[serde(default)]
+            1..=5 | SCHEMA_VERSION => {
+                self.schema_version = SCHEMA_VERSION;
+                Ok(self)
+            }
+            v => Err((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("schema_version {} não suportada (atual: {})", v, SCHEMA_VERSION),
+            )),
+        }
+    }
+}
+
+// Estado compartilhado pelas rotas HTTP
+#[derive(Clone)]
+struct AppState {
+    historico: SharedState,
+    // Quantidade máxima de registros mantidos (HISTORY_CAP)
+    capacidade: usize,
+    // Idade máxima dos registros (HISTORY_MAX_AGE_MINUTES), além da capacidade
+    idade_maxima: Option<TimeDelta>,
+    mqtt: AsyncClient,
+    faixas: FaixasValidacao,
+    // Leituras rejeitadas pela validação desde a inicialização
+    descartadas: Arc<AtomicU64>,
+    // Leituras aceitas pelo loop MQTT desde a inicialização (/metrics)
+    leituras_total: Arc<AtomicU64>,
+    // Cópia dos históricos exibida enquanto o modo "freeze" está ativo
+    congelado: Arc<Mutex<Option<Historicos>>>,
+    rotulos: Arc<Rotulos>,
+    heartbeat: UltimoHeartbeat,
+    // Idade máxima (s) da última leitura antes de marcar o dispositivo como OFFLINE
+    offline_apos: i64,
+    // Faixas de cor dos cards de temperatura e umidade
+    cores: CoresCards,
+    // Vira `true` no Ctrl-C/SIGTERM para encerrar as conexões /ws abertas
+    encerrando: watch::Receiver<bool>,
+    // Cada leitura aceita pelo loop MQTT (com o id do dispositivo), repassada aos clientes do /ws
+    ao_vivo: broadcast::Sender<(String, Registro)>,
+    // Chegada da última mensagem publicada no broker (leitura ou heartbeat), para o /healthz
+    ultima_mensagem: Arc<Mutex<Option<Instant>>>,
+    healthz_max: Duration,
+    // None quando o SQLite não abriu (sem persistência nem /api/aggregate)
+    banco: Option<BancoCompartilhado>,
+}
+
+impl AppState {
+    // Históricos exibidos pelo dashboard e pela API: o snapshot congelado ou o ao vivo
+    // O lock é mantido apenas durante o clone dos `Arc`
+    fn historicos_visiveis(&self) -> Historicos {
+        if let Some(snapshot) = travar(&self.congelado).as_ref() {
+            return snapshot.clone();
+        }
+
+        travar(&self.historico).clone()
+    }
+
+    // Histórico visível de um dispositivo (vazio se ele nunca publicou)
+    fn historico_visivel(&self, dispositivo: &str) -> Historico {
+        let congelado = travar(&self.congelado);
+        let historicos = match congelado.as_ref() {
+            Some(snapshot) => snapshot.get(dispositivo).cloned(),
+            None => travar(&self.historico).get(dispositivo).cloned(),
+        };
+
+        historicos.unwrap_or_default()
+    }
+}
+
+// Trava o mutex mesmo se envenenado: um panic em outra task não derruba o dashboard
+// nem o loop MQTT (os dados protegidos continuam consistentes entre operações)
+fn travar<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// Valida `Authorization: Bearer <token>` contra a variável de ambiente informada
+fn autorizar(headers: &HeaderMap, var: &str) -> Result<(), (StatusCode, String)> {
+    let Ok(token) = std::env::var(var) else {
+        return Err((StatusCode::FORBIDDEN, format!("Rota desativada ({} não definido)", var)));
+    };
+
+    let autorizado = headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|v| v == token);
+
+    if !autorizado {
+        return Err((StatusCode::UNAUTHORIZED, "Token inválido".to_string()));
+    }
+
+    Ok(())
+}
+
+// Corpo do POST /api/ota (também é o payload publicado no broker)
+#[derive(Deserialize, Serialize, Debug)]
+struct ComandoOta {
+    url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    checksum: Option<String>,
+}
+
+impl ComandoOta {
+    fn validar(&self) -> Result<(), String> {
+        let resto = self
+            .url
+            .strip_prefix("https://")
+            .or_else(|| self.url.strip_prefix("http://"))
+            .ok_or("URL deve começar com http:// ou https://")?;
+
+        let host = resto.split('/').next().unwrap_or("");
+        if host.is_empty() || resto.chars().any(char::is_whitespace) {
+            return Err("URL inválida".to_string());
+        }
+
+        if let Some(checksum) = &self.checksum {
+            // SHA-256 em hexadecimal
+            if checksum.len() != 64 || !checksum.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err("checksum deve ser um SHA-256 em hexadecimal (64 caracteres)".to_string());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    // RUST_LOG define o nível (ex.: "debug", "dashboard_iot=warn"); sem ele, NIVEL_LOG_PADRAO
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(NIVEL_LOG_PADRAO)))
+        .init();
+
+    // Panics (inclusive os do loop MQTT, que é reiniciado) vão para o log com o local
+    std::panic::set_hook(Box::new(|info| error!("Panic: {}", info)));
+
+    // 1. Inicializa o Estado
+    let faixas = FaixasValidacao::from_env();
+    let descartadas = Arc::new(AtomicU64::new(0));
+    let leituras_total = Arc::new(AtomicU64::new(0));
+    let capacidade = capacidade_historico_from_env();
+    info!("Faixas de validação: {:?}", faixas);
+    info!("Capacidade do histórico: {} registros", capacidade);
+    let idade_maxima = idade_maxima_historico_from_env();
+    if let Some(idade) = idade_maxima {
+        info!("Retenção por tempo: leituras com mais de {} min saem do histórico", idade.num_minutes());
+    }
+    let dedup_epsilon = deduplicacao_from_env();
+    if let Some(epsilon) = dedup_epsilon {
+        info!("Deduplicação ativa: leituras repetidas (±{}) não entram no histórico", epsilon);
+    }
+    let alertas = Alertas::from_env();
+    if let Some(a) = &alertas {
+        info!("Alertas via webhook: temperatura {:?}, umidade {:?}", a.temperatura, a.umidade);
+    }
+
+    // Sem o banco o servidor continua funcionando, apenas sem persistência
+    let caminho_banco = std::env::var(ENV_SQLITE_PATH).unwrap_or_else(|_| SQLITE_PATH_PADRAO.to_string());
+    let banco: Option<BancoCompartilhado> = match abrir_banco(&caminho_banco) {
+        Ok(conn) => Some(Arc::new(Mutex::new(conn))),
+        Err(e) => {
+            error!("Erro ao abrir {}: {} (persistência desativada)", caminho_banco, e);
+            None
+        }
+    };
+
+    let historicos_iniciais: Historicos = match banco.as_ref().map(|conn| carregar_recentes(&travar(conn), capacidade)) {
+        Some(Ok(mut historicos)) => {
+            for (dispositivo, registros) in historicos.iter_mut() {
+                aplicar_retencao(registros, capacidade, idade_maxima, Local::now());
+                info!("{} leituras de {} carregadas de {}", registros.len(), dispositivo, caminho_banco);
+            }
+            historicos.into_iter().map(|(id, registros)| (id, Arc::new(registros))).collect()
+        }
+        Some(Err(e)) => {
+            error!("Erro ao carregar leituras de {}: {}", caminho_banco, e);
+            HashMap::new()
+        }
+        None => HashMap::new(),
+    };
+
+    let estado_compartilhado: SharedState = Arc::new(Mutex::new(historicos_iniciais));
+
+    // 2. Configuração MQTT para a Nuvem (Render)
+    // Por padrão, o broker público para permitir conexão externa
+    let config_mqtt = ConfigMqtt::from_env();
+    info!("Broker MQTT: {:?}", config_mqtt);
+
+    let mut mqttoptions = MqttOptions::new("rust-render-client", config_mqtt.host.as_str(), config_mqtt.porta);
+    mqttoptions.set_keep_alive(Duration::from_secs(5));
+    mqttoptions.set_last_will(LastWill::new(&config_mqtt.topico_status, STATUS_OFFLINE, QoS::AtLeastOnce, true));
+
+    if config_mqtt.tls {
+        // rustls com os certificados raiz do sistema (feature `use-rustls` do rumqttc)
+        mqttoptions.set_transport(Transport::tls_with_default_config());
+    }
+
+    match (std::env::var(ENV_MQTT_USER), std::env::var(ENV_MQTT_PASS)) {
+        (Ok(usuario), Ok(senha)) => {
+            info!("MQTT: autenticando como {}", usuario);
+            mqttoptions.set_credentials(usuario, senha);
+        }
+        (Err(_), Err(_)) => {}
+        _ => warn!(
+            "Defina {} e {} juntos; conectando anonimamente",
+            ENV_MQTT_USER, ENV_MQTT_PASS
+        ),
+    }
+
+    // Um tópico por dispositivo: sensores/rusty_weather/<id>, ou cada tópico fixo listado em MQTT_TOPIC,
+    // mais o heartbeat
+    let topicos_leituras = config_mqtt.topicos;
+    let (client, eventloop) = AsyncClient::new(mqttoptions, topicos_leituras.len() + 1 + MQTT_CANAL_FOLGA);
+    let topico_status = config_mqtt.topico_status;
+
+    for topico in topicos_leituras.iter().map(String::as_str).chain([TOPICO_HEARTBEAT]) {
+        if let Err(e) = client.subscribe(topico, config_mqtt.qos).await {
+            error!("Erro ao assinar {}: {:?}", topico, e);
+        }
+    }
+    info!("Assinando leituras em: {}", topicos_leituras.join(", "));
+
+    let heartbeat: UltimoHeartbeat = Arc::new(Mutex::new(HashMap::new()));
+
+    let janela_agregado: JanelaAgregado = Arc::new(Mutex::new(HashMap::new()));
+    let intervalo_agregado = intervalo_agregado_from_env();
+
+    let (ao_vivo, _) = broadcast::channel::<(String, Registro)>(AO_VIVO_CAPACIDADE_CANAL);
+
+    // 3. Loop MQTT (Processamento em Background)
+    // IMPORTANTE: O tokio::spawn deve estar DENTRO da main, antes do servidor web travar o processo.
+    let ultima_mensagem: Arc<Mutex<Option<Instant>>> = Arc::new(Mutex::new(None));
+    let contexto_mqtt = ContextoMqtt {
+        eventloop: Arc::new(tokio::sync::Mutex::new(eventloop)),
+        cliente: client.clone(),
+        topico_status_mqtt: topico_status.clone(),
+        topico_derivadas: config_mqtt.topico_derivadas,
+        topicos_leituras,
+        estado_para_mqtt: estado_compartilhado.clone(),
+        janela_para_mqtt: janela_agregado.clone(),
+        descartadas_mqtt: descartadas.clone(),
+        leituras_total_mqtt: leituras_total.clone(),
+        heartbeat_mqtt: heartbeat.clone(),
+        ao_vivo_mqtt: ao_vivo.clone(),
+        ultima_mensagem_mqtt: ultima_mensagem.clone(),
+        banco_mqtt: banco.clone(),
+        alertas: Arc::new(Mutex::new(alertas)),
+        faixas,
+        dedup_epsilon,
+        capacidade,
+        idade_maxima,
+        agregar: intervalo_agregado.is_some(),
+    };
+    tokio::spawn(supervisionar_mqtt(contexto_mqtt));
+
+    // Agregados periódicos (min/max/média) publicados no broker
+    if let Some(periodo) = intervalo_agregado {
+        let topico = std::env::var(ENV_MQTT_AGGREGATE_TOPIC).unwrap_or_else(|_| TOPICO_AGREGADO_PADRAO.to_string());
+        info!("Publicando agregados a cada {} min em {}", periodo.as_secs() / 60, topico);
+        tokio::spawn(task_agregado(client.clone(), janela_agregado, topico, periodo));
+    }
+
+    // 4. Configuração do Servidor Web
+    let (aviso_encerramento, encerrando) = watch::channel(false);
+    let cliente_encerramento = client.clone();
+
+    let app_state = AppState {
+        historico: estado_compartilhado,
+        capacidade,
+        idade_maxima,
+        offline_apos: offline_apos_from_env(),
+        cores: CoresCards::from_env(),
+        mqtt: client,
+        faixas,
+        descartadas,
+        leituras_total,
+        congelado: Arc::new(Mutex::new(None)),
+        rotulos: Arc::new(Rotulos::from_env()),
+        heartbeat,
+        ao_vivo,
+        encerrando,
+        ultima_mensagem,
+        healthz_max: Duration::from_secs(env_ou_padrao(ENV_HEALTHZ_MAX_SEGUNDOS, HEALTHZ_MAX_SEGUNDOS_PADRAO)),
+        banco,
+    };
+
+    let nivel_requisicoes = env_ou_padrao(ENV_LOG_REQUISICOES, NIVEL_LOG_REQUISICOES_PADRAO);
+
+    let app = Router::new()
+        .route("/", get(handler_dashboard))
+        .route("/chart.png", get(handler_chart_png))
+        .route("/export.csv", get(handler_export_csv))
+        .route("/download/raw", get(handler_download_raw))
+        .route("/ws", get(handler_ws))
+        .route("/metrics", get(handler_metrics))
+        .route("/healthz", get(handler_healthz))
+        .route("/api/current", get(handler_current))
+        .route("/api/history", get(handler_history))
+        .route("/api/history.json", get(handler_history))
+        .route("/api/devices", get(handler_devices))
+        .route("/api/stats", get(handler_stats))
+        .route("/api/config", get(handler_config))
+        .route("/api/series", get(handler_series))
+        .route("/api/aggregate", get(handler_aggregate))
+        .route("/api/divergencia", get(handler_divergencia))
+        .route("/api/import", post(handler_import))
+        .route("/api/ingest", post(handler_ingest))
+        .route("/api/snapshot", get(handler_snapshot).post(handler_restaurar_snapshot))
+        .route("/api/ota", post(handler_ota))
+        .route("/api/freeze", post(handler_freeze))
+        .route("/api/unfreeze", post(handler_unfreeze))
+        .route("/api/reset", post(handler_reset))
+        // Corpo com Content-Encoding: gzip (uploads do /api/ingest); sem o cabeçalho passa direto
+        .layer(RequestDecompressionLayer::new())
+        // gzip/deflate conforme o Accept-Encoding do cliente; PNG já é comprimido
+        .layer(
+            CompressionLayer::new().compress_when(
+                SizeAbove::new(COMPRESSAO_TAMANHO_MIN_BYTES)
+                    .and(NotForContentType::IMAGES)
+                    .and(NotForContentType::SSE)
+                    .and(NotForContentType::GRPC),
+            ),
+        )
+        // Por fora da compressão: a latência inclui o tempo de comprimir
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(DefaultMakeSpan::new().level(nivel_requisicoes))
+                .on_response(DefaultOnResponse::new().level(nivel_requisicoes).latency_unit(LatencyUnit::Millis)),
+        )
+        .with_state(app_state);
+
+    // Configuração de Porta para o Render (0.0.0.0:3000 por padrão)
+    let porta_http: u16 = env_ou_padrao(ENV_HTTP_PORT, PORTA_HTTP_PADRAO);
+    let listener = tokio::net::TcpListener::bind(("0.0.0.0", porta_http)).await.unwrap();
+    info!("Servidor Web rodando na nuvem (Porta {})", porta_http);
+    
+    axum::serve(listener, app)
+        .with_graceful_shutdown(aguardar_encerramento(cliente_encerramento, aviso_encerramento, topico_status))
+        .await
+        .unwrap();
+
+    info!("Servidor encerrado");
+}
+
+// Ctrl-C (ou SIGTERM do systemd): avisa os clientes /ws e desconecta do broker.
+// As leituras já estão no SQLite (cada uma é gravada ao chegar), então não há o que descarregar.
+async fn aguardar_encerramento(client: AsyncClient, aviso: watch::Sender<bool>, topico_status: String) {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Erro ao aguardar Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let sigterm = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sinal) => {
+                sinal.recv().await;
+            }
+            Err(e) => {
+                error!("Erro ao aguardar SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let sigterm = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = sigterm => {},
+    }
+
+    info!("Encerrando: fechando conexões e desconectando do broker...");
+    let _ = aviso.send(true);
+
+    // Desconexão limpa não dispara o testamento: avisa o `offline` explicitamente
+    if let Err(e) = client.publish(topico_status, QoS::AtLeastOnce, true, STATUS_OFFLINE).await {
+        warn!("Erro ao publicar status offline: {:?}", e);
+    }
+
+    if let Err(e) = client.disconnect().await {
+        warn!("Erro ao desconectar do broker: {:?}", e);
+    }
+}
+
+// Tudo o que o loop MQTT usa; clonado a cada reinício da task
+#[derive(Clone)]
+struct ContextoMqtt {
+    // Compartilhado para sobreviver a um panic: o guard é liberado no unwind e a
+    // task reiniciada continua com a mesma conexão (e o mesmo AsyncClient)
+    eventloop: Arc<tokio::sync::Mutex<EventLoop>>,
+    // Publica o status `online` e as métricas derivadas
+    cliente: AsyncClient,
+    topico_status_mqtt: String,
+    topico_derivadas: String,
+    topicos_leituras: Vec<String>,
+    estado_para_mqtt: SharedState,
+    janela_para_mqtt: JanelaAgregado,
+    descartadas_mqtt: Arc<AtomicU64>,
+    leituras_total_mqtt: Arc<AtomicU64>,
+    heartbeat_mqtt: UltimoHeartbeat,
+    ao_vivo_mqtt: broadcast::Sender<(String, Registro)>,
+    ultima_mensagem_mqtt: Arc<Mutex<Option<Instant>>>,
+    banco_mqtt: Option<BancoCompartilhado>,
+    // Estado das transições de alerta, preservado entre reinícios
+    alertas: Arc<Mutex<Option<Alertas>>>,
+    faixas: FaixasValidacao,
+    dedup_epsilon: Option<f64>,
+    capacidade: usize,
+    idade_maxima: Option<TimeDelta>,
+    // Acumula as leituras na janela de agregação (só com a task de agregados ativa)
+    agregar: bool,
+}
+
+// Reinicia o loop MQTT se ele entrar em panic (payload inesperado, bug de lock...), em vez
+// de deixar a ingestão parada com o dashboard mostrando dados velhos. O servidor web segue.
+async fn supervisionar_mqtt(contexto: ContextoMqtt) {
+    loop {
+        match tokio::spawn(loop_mqtt(contexto.clone())).await {
+            Err(e) if e.is_panic() => {
+                error!(
+                    "Loop MQTT encerrado por panic; reiniciando em {}s",
+                    MQTT_REINICIO_APOS_PANIC.as_secs()
+                );
+                tokio::time::sleep(MQTT_REINICIO_APOS_PANIC).await;
+            }
+            // O loop não termina sozinho; cancelado só no encerramento do runtime
+            _ => return,
+        }
+    }
+}
+
+async fn loop_mqtt(contexto: ContextoMqtt) {
+    let ContextoMqtt {
+        eventloop,
+        cliente,
+        topico_status_mqtt,
+        topico_derivadas,
+        topicos_leituras,
+        estado_para_mqtt,
+        janela_para_mqtt,
+        descartadas_mqtt,
+        leituras_total_mqtt,
+        heartbeat_mqtt,
+        ao_vivo_mqtt,
+        ultima_mensagem_mqtt,
+        banco_mqtt,
+        alertas,
+        faixas,
+        dedup_epsilon,
+        capacidade,
+        idade_maxima,
+        agregar,
+    } = contexto;
+    let mut eventloop = eventloop.lock().await;
+    let mut espera = MQTT_BACKOFF_INICIAL;
+
+    loop {
+        match eventloop.poll().await {
+            Ok(notification) => {
+                espera = MQTT_BACKOFF_INICIAL;
+
+                // A cada (re)conexão, substitui o `offline` retido pelo testamento
+                if let Event::Incoming(Packet::ConnAck(_)) = notification {
+                    if let Err(e) = cliente.try_publish(&topico_status_mqtt, QoS::AtLeastOnce, true, STATUS_ONLINE) {
+                        warn!("Erro ao publicar status online: {:?}", e);
+                    }
+                    continue;
+                }
+
+                if let Event::Incoming(Packet::Publish(p)) = notification {
+                    // Os tópicos publicados pelo servidor podem cair no filtro das leituras (prefixo/+)
+                    if p.topic == topico_status_mqtt || p.topic == topico_derivadas {
+                        continue;
+                    }
+
+                    *travar(&ultima_mensagem_mqtt) = Some(Instant::now());
+
+                    if let Some(dispositivo) = dispositivo_do_heartbeat(&p.topic) {
+                        match serde_json::from_slice::<Heartbeat>(&p.payload) {
+                            Ok(hb) => {
+                                let mut heartbeats = travar(&heartbeat_mqtt);
+                                // Mesmo limite das leituras: ids inventados não crescem o mapa sem fim
+                                if heartbeats.contains_key(dispositivo) || heartbeats.len() < DISPOSITIVOS_MAX {
+                                    heartbeats.insert(dispositivo.to_string(), (hb, Local::now()));
+                                } else {
+                                    warn!("Heartbeat de {} ignorado: limite de {} dispositivos", dispositivo, DISPOSITIVOS_MAX);
+                                }
+                            }
+                            Err(e) => warn!("Heartbeat inválido de {}: {}", dispositivo, e),
+                        }
+                        continue;
+                    }
+
+                    // O dispositivo (último nível do tópico) é a origem da leitura no histórico
+                    let Some(dispositivo) = topicos_leituras.iter().find_map(|filtro| dispositivo_do_topico(filtro, &p.topic)) else {
+                        debug!("Tópico ignorado: {}", p.topic);
+                        continue;
+                    };
+
+                    if let Ok(dados_sensor) = serde_json::from_slice::<SensorData>(&p.payload) {
+                        // Mensagem retida: a última publicada antes de assinarmos, possivelmente antiga
+                        let retida = if p.retain { " (retida)" } else { "" };
+                        info!("Recebido de {} ({}){}: {:?}", dispositivo, p.topic, retida, dados_sensor);
+
+                        {
+                            let historicos = travar(&estado_para_mqtt);
+                            if !historicos.contains_key(dispositivo) && historicos.len() >= DISPOSITIVOS_MAX {
+                                warn!("Dispositivo {} ignorado: limite de {} atingido", dispositivo, DISPOSITIVOS_MAX);
+                                continue;
+                            }
+                        }
+
+                        if let Err(motivo) = faixas.validar(&dados_sensor) {
+                            let total = descartadas_mqtt.fetch_add(1, Ordering::Relaxed) + 1;
+                            warn!("Leitura descartada: {} (total: {})", motivo, total);
+                            continue;
+                        }
+
+                        if let Some(epsilon) = dedup_epsilon {
+                            let repetida = travar(&estado_para_mqtt)
+                                .get(dispositivo)
+                                .and_then(|history| history.back())
+                                .is_some_and(|anterior| dados_sensor.repete(&anterior.dados, epsilon));
+                            if repetida {
+                                debug!("Leitura repetida de {} ignorada", dispositivo);
+                                continue;
+                            }
+                        }
+                        
+                        let agora = Local::now();
+
+                        if let Some(conn) = &banco_mqtt {
+                            if let Err(e) = persistir_leitura(&travar(conn), dispositivo, &dados_sensor, &agora) {
+                                error!("Erro ao persistir leitura: {}", e);
+                            }
+                        }
+                        
+                        let novo_registro = Registro {
+                            dados: dados_sensor,
+                            horario: agora,
+                        };
+
+                        if agregar {
+                            travar(&janela_para_mqtt)
+                                .entry(dispositivo.to_string())
+                                .or_default()
+                                .push(dados_sensor);
+                        }
+
+                        let derivadas = {
+                            let mut historicos = travar(&estado_para_mqtt);
+                            let history = Arc::make_mut(historicos.entry(dispositivo.to_string()).or_default());
+                            history.push_back(novo_registro.clone());
+
+                            // Mantém apenas os últimos `capacidade` registros e, com
+                            // HISTORY_MAX_AGE_MINUTES, só os mais novos que a idade máxima
+                            aplicar_retencao(history, capacidade, idade_maxima, agora);
+                            MetricasDerivadas::calcular(dispositivo, history)
+                        };
+                        leituras_total_mqtt.fetch_add(1, Ordering::Relaxed);
+
+                        // try_publish: um publish().await aqui esperaria pelo próprio eventloop
+                        if let Some(derivadas) = derivadas {
+                            let payload = serde_json::to_vec(&derivadas).unwrap();
+                            if let Err(e) = cliente.try_publish(&topico_derivadas, QoS::AtLeastOnce, false, payload) {
+                                warn!("Erro ao publicar métricas derivadas: {:?}", e);
+                            }
+                        }
+
+                        if let Some(alertas) = travar(&alertas).as_mut() {
+                            for alerta in alertas.verificar(dispositivo, &novo_registro) {
+                                warn!(
+                                    "Alerta: {} de {} {:?} ({}, limite {:?})",
+                                    alerta.grandeza, alerta.dispositivo, alerta.estado, alerta.valor, alerta.limite
+                                );
+                                tokio::spawn(enviar_alerta(alertas.cliente.clone(), alertas.webhook.clone(), alerta));
+                            }
+                        }
+
+                        // Erro aqui só significa que não há clientes conectados
+                        let _ = ao_vivo_mqtt.send((dispositivo.to_string(), novo_registro));
+                    }
+                }
+            }
+            Err(e) => {
+                // O próximo poll() tenta reconectar
+                warn!("Erro MQTT: {:?} (nova tentativa em {}s)", e, espera.as_secs());
+                tokio::time::sleep(espera).await;
+                espera = (espera * 2).min(MQTT_BACKOFF_MAX);
+            }
+        }
+    }
+}
+
+async fn task_agregado(client: AsyncClient, janela: JanelaAgregado, topico: String, periodo: Duration) {
+    let mut intervalo = tokio::time::interval(periodo);
+    // O primeiro tick é imediato; descartamos para fechar a primeira janela completa
+    intervalo.tick().await;
+    let mut inicio = Local::now();
+
+    loop {
+        intervalo.tick().await;
+        let fim = Local::now();
+
+        let janelas = std::mem::take(&mut *travar(&janela));
+        let (texto_inicio, texto_fim) = (inicio.format("%H:%M:%S").to_string(), fim.format("%H:%M:%S").to_string());
+        inicio = fim;
+
+        if janelas.is_empty() {
+            debug!("Agregado: nenhuma leitura na janela, nada publicado");
+            continue;
+        }
+
+        for (dispositivo, leituras) in janelas {
+            let Some(agregado) = Agregado::calcular(dispositivo, &leituras, texto_inicio.clone(), texto_fim.clone()) else {
+                continue;
+            };
+
+            let payload = serde_json::to_vec(&agregado).unwrap();
+            match client.publish(&topico, QoS::AtLeastOnce, false, payload).await {
+                Ok(()) => info!("Agregado de {} publicado ({} amostras)", agregado.dispositivo, agregado.amostras),
+                Err(e) => error!("Erro ao publicar agregado: {:?}", e),
+            }
+        }
+    }
+}
+
+async fn handler_dashboard(
+    State(state): State<AppState>,
+    Query(params): Query<DashboardParams>,
+    Query(pressao): Query<PressaoParams>,
+) -> Html<String> {
+    let mut historicos: Vec<(String, Historico)> = state.historicos_visiveis().into_iter().collect();
+    historicos.sort_by(|a, b| a.0.cmp(&b.0));
+    // Antes da primeira leitura, mostra o dispositivo padrão vazio em vez de uma página em branco
+    if historicos.is_empty() {
+        historicos.push((DISPOSITIVO_PADRAO.to_string(), Historico::default()));
+    }
+    let nivel_mar = pressao.nivel_mar();
+    if nivel_mar {
+        for (_, history) in historicos.iter_mut() {
+            *history = historico_nivel_mar(history);
+        }
+    }
+
+    let congelado_html = if travar(&state.congelado).is_some() {
+        r#"<div class="congelado">❄️ Histórico congelado — ingestão continua em segundo plano</div>"#
+    } else {
+        ""
+    };
+
+    // Só a exibição muda com ?unit=f e ?punit=; o rótulo configurado vale para °C e hPa
+    let unidade = params.unidade();
+    let unidade_pressao = pressao.unidade();
+    let unidades = Unidades { temperatura: unidade, pressao: unidade_pressao };
+    let tema = params.tema();
+    let mut rotulos = (*state.rotulos).clone();
+    if unidade == UnidadeTemperatura::Fahrenheit {
+        rotulos.temperatura.unidade = unidade.simbolo().to_string();
+    }
+    if unidade_pressao != UnidadePressao::Hpa {
+        rotulos.pressao.unidade = unidade_pressao.simbolo().to_string();
+    }
+    if nivel_mar {
+        rotulos.pressao.completo = format!("{} (nível do mar)", rotulos.pressao.completo);
+    }
+    let rotulos = &rotulos;
+
+    let secoes: String = historicos
+        .iter()
+        .map(|(id, history)| {
+            render_secao_dispositivo(id, history, rotulos, params.view, unidades, &state)
+        })
+        .collect();
+
+    // Cada link muda um parâmetro e preserva os demais
+    let link = |view: Visualizacao, unidade: UnidadeTemperatura, pressao: UnidadePressao, msl: bool, tema: Tema| {
+        format!(
+            "?view={}&unit={}&punit={}&msl={}&theme={}",
+            view.parametro(),
+            unidade.parametro(),
+            pressao.parametro(),
+            u8::from(msl),
+            tema.parametro()
+        )
+    };
+    let (tema_oposto, rotulo_tema) = tema.alternar();
+    let views_html = format!(
+        r#"<a href="{}">Tabela</a> | <a href="{}">Lista</a> · <a href="{}">°C</a> | <a href="{}">°F</a> · <a href="{}">hPa</a> | <a href="{}">inHg</a> | <a href="{}">mmHg</a> · <a href="{}">Estação</a> | <a href="{}">Nível do mar</a> · <a href="{}">{}</a>"#,
+        link(Visualizacao::Table, unidade, unidade_pressao, nivel_mar, tema),
+        link(Visualizacao::List, unidade, unidade_pressao, nivel_mar, tema),
+        link(params.view, UnidadeTemperatura::Celsius, unidade_pressao, nivel_mar, tema),
+        link(params.view, UnidadeTemperatura::Fahrenheit, unidade_pressao, nivel_mar, tema),
+        link(params.view, unidade, UnidadePressao::Hpa, nivel_mar, tema),
+        link(params.view, unidade, UnidadePressao::InHg, nivel_mar, tema),
+        link(params.view, unidade, UnidadePressao::MmHg, nivel_mar, tema),
+        link(params.view, unidade, unidade_pressao, false, tema),
+        link(params.view, unidade, unidade_pressao, true, tema),
+        link(params.view, unidade, unidade_pressao, nivel_mar, tema_oposto),
+        rotulo_tema
+    );
+
+    let html = format!(
+        r#"
+        <!DOCTYPE html>
+        <html>
+        <head>
+            <title>Rusty Weather Station</title>
+            <meta name="viewport" content="width=device-width, initial-scale=1">
+            <style>
+                body {{ font-family: sans-serif; background: #f4f4f9; padding: 20px; text-align: center; }}
+                .cards {{ display: flex; flex-wrap: wrap; justify-content: center; gap: 20px; margin-bottom: 40px; }}
+                .card {{ background: white; padding: 20px; border-radius: 10px; box-shadow: 0 2px 5px rgba(0,0,0,0.1); flex: 0 1 180px; min-width: 120px; box-sizing: border-box; }}
+                .val {{ font-size: 2.5rem; font-weight: bold; margin: 10px 0; }}
+                .ts {{ color: #888; margin-bottom: 20px; }}
+                .views {{ margin-bottom: 10px; }}
+                .divergencia {{ margin: -20px auto 30px; color: #555; }}
+                .divergencia.alerta {{ color: #c0392b; font-weight: bold; }}
+                .congelado {{ background: #d6eaf8; padding: 8px; margin-bottom: 20px; }}
+                .heartbeat {{ color: #27ae60; margin-bottom: 10px; }}
+                .heartbeat.offline {{ color: #c0392b; }}
+                .dispositivo {{ margin-bottom: 50px; }}
+                .estatisticas {{ display: flex; flex-wrap: wrap; justify-content: center; gap: 10px 30px; margin: -20px auto 30px; color: #555; font-size: 0.9rem; }}
+                .grafico {{ display: block; width: 80%; max-width: 600px; margin: 0 auto 20px; background: white; border-radius: 10px; box-shadow: 0 2px 5px rgba(0,0,0,0.1); }}
+                .grafico text {{ font-size: 11px; fill: #888; }}
+                .tendencia {{ margin: -20px auto 30px; color: #555; }}
+                .volatilidade {{ display: inline-block; width: 60px; height: 6px; background: #ddd; border-radius: 3px; vertical-align: middle; overflow: hidden; }}
+                .volatilidade span {{ display: block; height: 100%; background: #e67e22; }}
+                .conforto {{ display: inline-block; color: white; padding: 8px 24px; border-radius: 10px; margin-bottom: 20px; font-weight: bold; }}
+                .conforto.confortavel {{ background: #27ae60; }}
+                .conforto.seco {{ background: #e67e22; }}
+                .conforto.umido {{ background: #2980b9; }}
+                .conforto.quente {{ background: #c0392b; }}
+                .conforto.frio {{ background: #5dade2; }}
+                .bateria-baixa .val {{ color: #c0392b; }}
+                .nivel-normal .val {{ color: #27ae60; }}
+                .nivel-atencao .val {{ color: #e67e22; }}
+                .nivel-critico .val {{ color: #c0392b; }}
+                .offline {{ background: #c0392b; color: white; padding: 2px 8px; border-radius: 4px; font-weight: bold; }}
+                
+                .tabela-rolagem {{ width: 80%; max-width: 600px; margin: 0 auto; overflow-x: auto; }}
+                table {{ border-collapse: collapse; width: 100%; background: white; }}
+                th, td {{ padding: 12px; border-bottom: 1px solid #ddd; text-align: center; }}
+                th {{ background-color: #333; color: white; }}
+                tr:nth-child(even) {{ background-color: #f9f9f9; }}
+
+                ul.lista {{ list-style: none; padding: 0; margin: 0 auto; max-width: 600px; background: white; }}
+                ul.lista li {{ padding: 8px; border-bottom: 1px solid #ddd; font-family: monospace; }}
+
+                /* Celular: usa a largura toda e reduz os números */
+                @media (max-width: 600px) {{
+                    body {{ padding: 10px; }}
+                    .cards {{ gap: 10px; }}
+                    .card {{ padding: 12px; }}
+                    .val {{ font-size: 1.8rem; }}
+                    .grafico, .tabela-rolagem {{ width: 100%; }}
+                    th, td {{ padding: 8px; white-space: nowrap; }}
+                }}
+                {}
+            </style>
+        </head>
+        <body>
+            <h1>Rusty Weather Dashboard 🦀</h1>
+            {}
+            <div class="views">{}</div>
+            {}
+
+            <script>
+                // Atualização ao vivo: cada leitura nova chega pelo /ws
+                const UNIDADES = {{ temperatura: {}, umidade: {}, pressao: {} }};
+                const CAPACIDADE = {};
+                const OFFLINE_APOS_MS = {} * 1000;
+                const CAMPOS = ["temperatura", "umidade", "pressao"];
+                const TENDENCIA_LIMIAR_HPA = {};
+                const VOLATILIDADE_LIMIAR_HPA = {};
+                const CONFORTO = {};
+                const BATERIA_BAIXA_V = {};
+                // Faixas de cor dos cards (em °C e %, antes da conversão para exibir)
+                const CORES = {};
+                const nivelCor = (c, v) => {{
+                    const l = CORES[c];
+                    if (v === null || v === undefined) return "";
+                    return !(v >= l.critico.min && v <= l.critico.max) ? "nivel-critico" :
+                        !(v >= l.atencao.min && v <= l.atencao.max) ? "nivel-atencao" : "nivel-normal";
+                }};
+                const GRAFICO = {{ largura: {}, altura: {}, margem: {} }};
+                // Dados chegam em °C; converte só para exibir
+                const FAHRENHEIT = {};
+                const TEMPERATURAS = ["temperatura", "ponto_orvalho", "sensacao_termica"];
+                // Pressão chega em hPa; `fator` converte para a unidade de ?punit=
+                const PRESSAO = {{ fator: {}, casas: {}, simbolo: {} }};
+                // ?msl=1: mostra `pressao_nivel_mar` (sem altitude, fica a pressão da estação)
+                const NIVEL_MAR = {};
+                // null: dispositivo sem o sensor da grandeza
+                const exibir = (c, v) => v === null || v === undefined ? "—" :
+                    c === "pressao" ? (v * PRESSAO.fator).toFixed(PRESSAO.casas) :
+                    (FAHRENHEIT && TEMPERATURAS.includes(c) ? v * 9 / 5 + 32 : v).toFixed(1);
+
+                // Instante (relógio do navegador) da última leitura de cada dispositivo. Parte da
+                // idade calculada pelo servidor, não do timestamp, para não depender dos relógios baterem.
+                const ultimaLeitura = {{}};
+                document.querySelectorAll("section.dispositivo").forEach((s) => {{
+                    if (s.dataset.idadeMs !== "") ultimaLeitura[s.id.slice("dispositivo-".length)] = Date.now() - Number(s.dataset.idadeMs);
+                }});
+                // "há 4 s", "há 3 min", "há 2 h"
+                const formatarIdade = (ms) => {{
+                    const s = Math.max(0, Math.floor(ms / 1000));
+                    return "há " + (s < 60 ? s + " s" : s < 3600 ? Math.floor(s / 60) + " min" : Math.floor(s / 3600) + " h");
+                }};
+                const atualizarIdades = () => {{
+                    for (const [d, t] of Object.entries(ultimaLeitura)) {{
+                        document.getElementById("offline-" + d).hidden = Date.now() - t <= OFFLINE_APOS_MS;
+                        document.getElementById("idade-" + d).textContent = "(" + formatarIdade(Date.now() - t) + ")";
+                    }}
+                }};
+                atualizarIdades();
+                setInterval(atualizarIdades, 1000);
+
+                // Mesmo cálculo de pontos_grafico no servidor
+                const atualizarGrafico = (d, valor) => {{
+                    const svg = document.getElementById("grafico-" + d);
+                    const valores = JSON.parse(svg.dataset.valores);
+                    valores.push(valor);
+                    while (valores.length > CAPACIDADE) valores.shift();
+                    svg.dataset.valores = JSON.stringify(valores);
+
+                    const min = Math.min(...valores), max = Math.max(...valores);
+                    const y = (v) => max > min
+                        ? GRAFICO.margem + (max - v) / (max - min) * (GRAFICO.altura - 2 * GRAFICO.margem)
+                        : GRAFICO.altura / 2;
+                    const pontos = valores.length === 1
+                        ? [[GRAFICO.margem, y(valor)], [GRAFICO.largura - GRAFICO.margem, y(valor)]]
+                        : valores.map((v, i) => [GRAFICO.margem + i * (GRAFICO.largura - 2 * GRAFICO.margem) / (valores.length - 1), y(v)]);
+
+                    document.getElementById("grafico-linha-" + d).setAttribute("points", pontos.map(([px, py]) => px.toFixed(1) + "," + py.toFixed(1)).join(" "));
+                    document.getElementById("grafico-max-" + d).textContent = max.toFixed(1) + " " + UNIDADES.temperatura;
+                    document.getElementById("grafico-min-" + d).textContent = min.toFixed(1) + " " + UNIDADES.temperatura;
+                }};
+
+                const ws = new WebSocket((location.protocol === "https:" ? "wss://" : "ws://") + location.host + "/ws");
+                ws.onmessage = (evento) => {{
+                    const r = JSON.parse(evento.data);
+                    const d = r.dispositivo;
+                    if (NIVEL_MAR) r.pressao = r.pressao_nivel_mar ?? r.pressao;
+                    // Dispositivo novo: a seção dele ainda não existe na página
+                    if (!document.getElementById("dispositivo-" + d)) {{
+                        location.reload();
+                        return;
+                    }}
+                    const valores = CAMPOS.map((c) => r[c] === null || r[c] === undefined ? "—" : exibir(c, r[c]) + " " + UNIDADES[c]);
+
+                    [...CAMPOS, "ponto_orvalho", "sensacao_termica", "umidade_absoluta"].forEach((c) => document.getElementById("val-" + d + "-" + c).textContent = exibir(c, r[c]));
+                    const horario = document.getElementById("horario-" + d);
+                    horario.textContent = r.horario;
+                    horario.dateTime = r.timestamp;
+                    ["temperatura", "umidade"].forEach((c) => document.getElementById("card-" + d + "-" + c).className = "card " + nivelCor(c, r[c]));
+                    const temBateria = r.bateria !== null && r.bateria !== undefined;
+                    document.getElementById("val-" + d + "-bateria").textContent = temBateria ? r.bateria.toFixed(2) : "—";
+                    document.getElementById("card-" + d + "-bateria").classList.toggle("bateria-baixa", temBateria && r.bateria < BATERIA_BAIXA_V);
+                    const conforto = document.getElementById("conforto-" + d);
+                    conforto.className = "conforto " + r.conforto;
+                    conforto.textContent = CONFORTO[r.conforto];
+                    conforto.hidden = false;
+                    ultimaLeitura[d] = Date.now();
+                    atualizarIdades();
+
+                    const historico = document.getElementById("historico-" + d);
+                    let item;
+                    if (historico.tagName === "TBODY") {{
+                        item = document.createElement("tr");
+                        [r.horario, ...valores].forEach((v) => item.insertCell().textContent = v);
+                    }} else {{
+                        item = document.createElement("li");
+                        item.textContent = r.horario + " — " + valores.join(" / ");
+                    }}
+                    historico.prepend(item);
+                    while (historico.children.length > CAPACIDADE) historico.lastElementChild.remove();
+                    atualizarGrafico(d, Number(exibir("temperatura", r.temperatura)));
+
+                    // Estatísticas dependem da janela inteira: recalculadas pelo servidor
+                    fetch("/api/stats?device=" + d + (NIVEL_MAR ? "&msl=1" : ""))
+                        .then((resp) => resp.status === 200 ? resp.json() : null)
+                        .then((s) => {{
+                            if (!s) return;
+                            CAMPOS.forEach((c) => {{
+                                const e = s[c];
+                                document.getElementById("stats-" + d + "-" + c).textContent = e === null ? "—" :
+                                    "mín " + exibir(c, e.min) + " · média " + exibir(c, e.media) + " · máx " + exibir(c, e.max);
+                            }});
+                            const v = s.variacao_pressao;
+                            document.getElementById("tendencia-" + d).textContent = v === null ? "—" :
+                                (v >= TENDENCIA_LIMIAR_HPA ? "↑ melhorando" : v <= -TENDENCIA_LIMIAR_HPA ? "↓ piorando" : "→ estável") +
+                                " (" + (v >= 0 ? "+" : "") + exibir("pressao", v) + " " + PRESSAO.simbolo + ")";
+                            const dp = s.desvio_pressao;
+                            document.getElementById("confianca-" + d).textContent = dp === null ? "—" :
+                                (dp > VOLATILIDADE_LIMIAR_HPA ? "volátil" : "firme") + " (σ " + exibir("pressao", dp) + " " + PRESSAO.simbolo + ")";
+                            document.getElementById("volatilidade-" + d).style.width =
+                                (dp === null ? 0 : Math.min(dp / (2 * VOLATILIDADE_LIMIAR_HPA), 1) * 100) + "%";
+                        }});
+                }};
+                // Conexão perdida (servidor reiniciado, rede): recarrega para reconectar
+                ws.onclose = () => setTimeout(() => location.reload(), 3000);
+            </script>
+        </body>
+        </html>
+        "#,
+        tema.css(),
+        congelado_html,
+        views_html,
+        secoes,
+        serde_json::to_string(&rotulos.temperatura.unidade).unwrap(),
+        serde_json::to_string(&rotulos.umidade.unidade).unwrap(),
+        serde_json::to_string(&rotulos.pressao.unidade).unwrap(),
+        state.capacidade,
+        state.offline_apos,
+        TENDENCIA_PRESSAO_LIMIAR_HPA,
+        VOLATILIDADE_PRESSAO_LIMIAR_HPA,
+        conforto_js(),
+        BATERIA_BAIXA_V,
+        serde_json::to_string(&state.cores).unwrap(),
+        GRAFICO_LARGURA,
+        GRAFICO_ALTURA,
+        GRAFICO_MARGEM,
+        unidade == UnidadeTemperatura::Fahrenheit,
+        unidade_pressao.fator(),
+        unidade_pressao.casas(),
+        serde_json::to_string(unidade_pressao.simbolo()).unwrap(),
+        nivel_mar
+    );
+
+    Html(html)
+}
+
+// Status do heartbeat de um dispositivo; vazio se ele nunca publicou um
+fn render_heartbeat(heartbeat: Option<&(Heartbeat, DateTime<Local>)>) -> String {
+    match heartbeat {
+        Some(&(hb, recebido)) if (Local::now() - recebido).num_seconds() <= HEARTBEAT_TIMEOUT_SEGUNDOS => {
+            let confiabilidade = match (hb.leituras_bmp280, hb.leituras_umidade) {
+                (Some(bmp280), Some(umidade)) => format!(
+                    r#"<div class="heartbeat">Leituras válidas — BMP280: {}, umidade: {}</div>"#,
+                    bmp280.resumo(),
+                    umidade.resumo()
+                ),
+                _ => String::new(),
+            };
+            let divergencia = match hb.divergencia_temperatura_c {
+                Some(delta) if hb.sensores_divergentes => format!(
+                    r#"<div class="heartbeat offline">⚠️ Firmware: BMP280 e DHT divergem em {:.1} °C — autoaquecimento ou sensor com defeito?</div>"#,
+                    delta
+                ),
+                _ => String::new(),
+            };
+            format!(
+                r#"<div class="heartbeat">📡 Dispositivo online — uptime {}s, erros BMP280: {}, DHT11: {} (heartbeat às {})</div>{}{}"#,
+                hb.uptime_s,
+                hb.erros_bmp280,
+                hb.erros_dht11,
+                recebido.format("%H:%M:%S"),
+                confiabilidade,
+                divergencia
+            )
+        }
+        Some(&(_, recebido)) => format!(
+            r#"<div class="heartbeat offline">📴 Dispositivo sem heartbeat desde {}</div>"#,
+            recebido.format("%H:%M:%S")
+        ),
+        None => String::new(),
+    }
+}
+
+// Cards, divergência e histórico de um dispositivo
+fn render_secao_dispositivo(
+    id: &str,
+    history: &VecDeque<Registro>,
+    rotulos: &Rotulos,
+    view: Visualizacao,
+    unidades: Unidades,
+    state: &AppState,
+) -> String {
+    let heartbeat_html = render_heartbeat(travar(&state.heartbeat).get(id));
+    // Ids que não vieram do MQTT (banco antigo, por exemplo) podem ter qualquer caractere
+    let id = &escapar_html(id);
+    let unidade = unidades.temperatura;
+    let atual = history.back();
+    let dados = atual.map_or(
+        SensorData {
+            temperatura: 0.0,
+            umidade: None,
+            pressao: None,
+            temperatura_dht11: None,
+            bateria: None,
+            altitude: None,
+        },
+        |reg| reg.dados,
+    );
+    let horario = atual.map_or("--:--:--".to_string(), |reg| reg.horario.format("%H:%M:%S").to_string());
+
+    // Idade da última leitura; o script continua a contagem no navegador
+    let idade_ms = atual.map(|reg| (Local::now() - reg.horario).num_milliseconds());
+    let offline = idade_ms.is_some_and(|ms| ms > state.offline_apos * 1000);
+
+    // Sem leituras o banner fica escondido até a primeira chegar
+    let conforto = atual.map(|reg| Conforto::classificar(reg.dados.temperatura, reg.dados.umidade));
+    let conforto_html = format!(
+        r#"<div class="conforto {}" id="conforto-{id}"{}>{}</div>"#,
+        conforto.map_or("", Conforto::chave),
+        if conforto.is_some() { "" } else { " hidden" },
+        conforto.map_or("", Conforto::rotulo)
+    );
+
+    // Mais recente primeiro, igual para as duas visualizações
+    let linhas: Vec<LinhaHistorico> = history
+        .iter()
+        .rev()
+        .map(|reg| LinhaHistorico::from_registro(reg, rotulos, unidades))
+        .collect();
+
+    let divergencia_html = match Divergencia::calcular(&dados) {
+        Some(d) => {
+            let (bmp280, dht11) = (unidade.converter(d.temperatura_bmp280), unidade.converter(d.temperatura_dht11));
+            let (delta, simbolo) = ((bmp280 - dht11).abs(), unidade.simbolo());
+            if d.alerta {
+                format!(
+                    r#"<div class="divergencia alerta">⚠️ Sensores divergentes: BMP280 {:.1} {simbolo} vs DHT11 {:.1} {simbolo} (Δ {:.1} {simbolo})</div>"#,
+                    bmp280, dht11, delta
+                )
+            } else {
+                format!(
+                    r#"<div class="divergencia">BMP280 {:.1} {simbolo} / DHT11 {:.1} {simbolo} (Δ {:.1} {simbolo})</div>"#,
+                    bmp280, dht11, delta
+                )
+            }
+        }
+        None => String::new(),
+    };
+
+    let resumo = resumo_historico(id, history);
+    let (confianca, volatilidade) = formatar_confianca(resumo.as_ref().and_then(|r| r.desvio_pressao), unidades.pressao);
+    let estatistica = |campo: fn(&Agregado) -> Option<&Estatistica>, converter: &dyn Fn(f64) -> f64, casas: usize| {
+        resumo.as_ref().and_then(campo).map_or("—".to_string(), |e| formatar_estatistica(e, converter, casas))
+    };
+    let sem_conversao = |v: f64| v;
+    let temperatura = |c: f64| unidade.converter(c);
+    let pressao = |hpa: f64| unidades.pressao.converter(hpa);
+
+    let simbolo = unidade.simbolo();
+
+    let historico_html = match view {
+        Visualizacao::Table => render_tabela(id, &linhas, rotulos),
+        Visualizacao::List => render_lista(id, &linhas),
+    };
+
+    // Ordem cronológica, como no eixo X do gráfico; com mais pontos que pixels, mínimo e
+    // máximo de cada par de pixels (os picos continuam visíveis)
+    let temperaturas: Vec<f64> = history.iter().map(|reg| unidade.converter(reg.dados.temperatura)).collect();
+    let temperaturas = decimar(&temperaturas, Decimacao::MinMax(GRAFICO_COLUNAS), |v| *v);
+    let grafico_html = render_grafico_svg(id, &temperaturas, simbolo);
+
+    format!(
+        r#"<section class="dispositivo" id="dispositivo-{id}" data-idade-ms="{}">
+            <h2>📟 {id}</h2>
+            <div class="ts">Última atualização: <strong><time id="horario-{id}" datetime="{}">{}</time></strong> <span class="idade" id="idade-{id}"></span> <span class="offline" id="offline-{id}"{}>OFFLINE</span></div>
+            {}
+            {}
+
+            <div class="cards">
+                <div class="card {}" id="card-{id}-temperatura"><div style="color: #e74c3c">{}</div><div class="val" id="val-{id}-temperatura">{:.1}</div><div>{}</div></div>
+                <div class="card {}" id="card-{id}-umidade"><div style="color: #3498db">{}</div><div class="val" id="val-{id}-umidade">{}</div><div>{}</div></div>
+                <div class="card"><div style="color: #2ecc71">{}</div><div class="val" id="val-{id}-pressao">{}</div><div>{}</div></div>
+                <div class="card"><div style="color: #9b59b6">Orvalho</div><div class="val" id="val-{id}-ponto_orvalho">{}</div><div>{simbolo}</div></div>
+                <div class="card"><div style="color: #e67e22">Sensação</div><div class="val" id="val-{id}-sensacao_termica">{}</div><div>{simbolo}</div></div>
+                <div class="card"><div style="color: #1abc9c">Umid. abs.</div><div class="val" id="val-{id}-umidade_absoluta">{}</div><div>g/m³</div></div>
+                <div class="card{}" id="card-{id}-bateria"><div style="color: #7f8c8d">Bateria</div><div class="val" id="val-{id}-bateria">{}</div><div>V</div></div>
+            </div>
+            {}
+            <div class="tendencia">Tendência da pressão: <strong id="tendencia-{id}">{}</strong> · confiança: <span id="confianca-{id}">{}</span> <span class="volatilidade"><span id="volatilidade-{id}" style="width: {:.0}%"></span></span></div>
+            <div class="estatisticas">
+                <span>{} <span id="stats-{id}-temperatura">{}</span> {}</span>
+                <span>{} <span id="stats-{id}-umidade">{}</span> {}</span>
+                <span>{} <span id="stats-{id}-pressao">{}</span> {}</span>
+            </div>
+
+            <h3>Histórico Recente (Últimas {} leituras)</h3>
+            {}
+            {}
+        </section>"#,
+        // Sem leituras não há idade (e o selo fica escondido)
+        idade_ms.map_or(String::new(), |ms| ms.to_string()),
+        atual.map_or(String::new(), |reg| reg.horario.to_rfc3339()),
+        horario,
+        if offline { "" } else { " hidden" },
+        heartbeat_html,
+        conforto_html,
+        classe_nivel(&state.cores.temperatura, atual.map(|reg| reg.dados.temperatura)),
+        rotulos.temperatura.curto,
+        unidade.converter(dados.temperatura),
+        rotulos.temperatura.unidade,
+        classe_nivel(&state.cores.umidade, dados.umidade),
+        rotulos.umidade.curto,
+        formatar_opcional(dados.umidade, ""),
+        rotulos.umidade.unidade,
+        rotulos.pressao.curto,
+        formatar_pressao(dados.pressao, unidades.pressao, ""),
+        rotulos.pressao.unidade,
+        // Derivados exigem umidade; sem ela (ou sem leituras) o card mostra "—"
+        formatar_opcional(dados.umidade.map(|rh| unidade.converter(dew_point(dados.temperatura, rh))), ""),
+        formatar_opcional(dados.umidade.map(|rh| unidade.converter(heat_index(dados.temperatura, rh))), ""),
+        formatar_opcional(dados.umidade.map(|rh| absolute_humidity(dados.temperatura, rh)), ""),
+        if dados.bateria.is_some_and(|v| v < BATERIA_BAIXA_V) { " bateria-baixa" } else { "" },
+        // Duas casas: a descarga de uma Li-ion cabe em ~1 V
+        dados.bateria.map_or("—".to_string(), |v| format!("{:.2}", v)),
+        divergencia_html,
+        formatar_tendencia(resumo.as_ref().and_then(|r| r.variacao_pressao), unidades.pressao),
+        confianca,
+        volatilidade,
+        rotulos.temperatura.curto,
+        estatistica(|r| Some(&r.temperatura), &temperatura, 1),
+        rotulos.temperatura.unidade,
+        rotulos.umidade.curto,
+        estatistica(|r| r.umidade.as_ref(), &sem_conversao, 1),
+        rotulos.umidade.unidade,
+        rotulos.pressao.curto,
+        estatistica(|r| r.pressao.as_ref(), &pressao, unidades.pressao.casas()),
+        rotulos.pressao.unidade,
+        state.capacidade,
+        grafico_html,
+        historico_html,
+    )
+}
+
+// Coordenadas da polyline: X é a ordem da leitura e Y é escalado entre o mínimo e o máximo.
+// O script do dashboard repete este cálculo a cada leitura nova.
+fn pontos_grafico(valores: &[f64]) -> String {
+    let (min, max) = valores
+        .iter()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), &v| (min.min(v), max.max(v)));
+    let largura_util = GRAFICO_LARGURA - 2.0 * GRAFICO_MARGEM;
+    let altura_util = GRAFICO_ALTURA - 2.0 * GRAFICO_MARGEM;
+
+    // Sem variação (ou um ponto só) não há escala: a linha fica no meio
+    let y = |v: f64| {
+        if max > min {
+            GRAFICO_MARGEM + (max - v) / (max - min) * altura_util
+        } else {
+            GRAFICO_ALTURA / 2.0
+        }
+    };
+
+    match valores {
+        [] => String::new(),
+        // Um ponto só vira uma reta horizontal, já que a polyline precisa de dois
+        [v] => format!("{:.1},{:.1} {:.1},{:.1}", GRAFICO_MARGEM, y(*v), GRAFICO_LARGURA - GRAFICO_MARGEM, y(*v)),
+        _ => valores
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| {
+                let x = GRAFICO_MARGEM + i as f64 * largura_util / (valores.len() - 1) as f64;
+                format!("{:.1},{:.1}", x, y(v))
+            })
+            .collect::<Vec<_>>()
+            .join(" "),
+    }
+}
+
+// Gráfico de temperatura do histórico, sem JS externo; `data-valores` guarda a série para o script
+fn render_grafico_svg(id: &str, valores: &[f64], simbolo: &str) -> String {
+    let extremo = |f: fn(f64, f64) -> f64| valores.iter().copied().reduce(f);
+    let (rotulo_max, rotulo_min) = match (extremo(f64::max), extremo(f64::min)) {
+        (Some(max), Some(min)) => (format!("{:.1} {simbolo}", max), format!("{:.1} {simbolo}", min)),
+        _ => ("Sem leituras".to_string(), String::new()),
+    };
+
+    format!(
+        r##"<svg class="grafico" id="grafico-{id}" viewBox="0 0 {} {}" data-valores="{}" role="img" aria-label="Temperatura no histórico">
+                <polyline id="grafico-linha-{id}" fill="none" stroke="#e74c3c" stroke-width="2" points="{}"/>
+                <text id="grafico-max-{id}" x="4" y="12">{}</text>
+                <text id="grafico-min-{id}" x="4" y="{}">{}</text>
+            </svg>"##,
+        GRAFICO_LARGURA,
+        GRAFICO_ALTURA,
+        serde_json::to_string(valores).unwrap(),
+        pontos_grafico(valores),
+        rotulo_max,
+        GRAFICO_ALTURA - 4.0,
+        rotulo_min,
+    )
+}
+
+// Mesmo formato aceito pelo POST /api/import, em ordem cronológica e com data completa no horario
+async fn handler_export_csv(
+    State(state): State<AppState>,
+    Query(dispositivo): Query<DispositivoParams>,
+) -> Response {
+    let mut csv = String::from("horario,temperatura,umidade,pressao\n");
+    for reg in state.historico_visivel(dispositivo.id()).iter() {
+        // Grandeza ausente vira campo vazio, como aceito na importação
+        let opcional = |v: Option<f64>| v.map_or(String::new(), |v| format!("{:.2}", v));
+        csv.push_str(&format!(
+            "{},{:.2},{},{}\n",
+            reg.horario.to_rfc3339_opts(SecondsFormat::Secs, false),
+            reg.dados.temperatura,
+            opcional(reg.dados.umidade),
+            opcional(reg.dados.pressao)
+        ));
+    }
+
+    (
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (header::CONTENT_DISPOSITION, "attachment; filename=\"rusty-weather.csv\""),
+        ],
+        csv,
+    )
+        .into_response()
+}
+
+// Mesmo layout que `gravar_bmp280` grava no SPIFFS (`timestamp,temperatura,pressao,altitude`,
+// época Unix e sem cabeçalho), para ferramentas feitas para os arquivos do dispositivo
+async fn handler_download_raw(
+    State(state): State<AppState>,
+    Query(dispositivo): Query<DispositivoParams>,
+) -> Response {
+    let mut csv = String::new();
+    // Sem pressão não houve leitura do BMP280; a linha do firmware não existiria
+    for reg in state.historico_visivel(dispositivo.id()).iter() {
+        let Some(pressao) = reg.dados.pressao else {
+            continue;
+        };
+        // Leituras sem altitude (firmware antigo) usam a fórmula do firmware com a referência padrão
+        let altitude = reg
+            .dados
+            .altitude
+            .unwrap_or_else(|| ALTITUDE_ESCALA_M * (1.0 - (pressao / ALTITUDE_REFERENCIA_HPA).powf(ALTITUDE_EXPOENTE)));
+        csv.push_str(&format!(
+            "{},{:.2},{:.2},{:.2}\n",
+            reg.horario.timestamp(),
+            reg.dados.temperatura,
+            pressao,
+            altitude
+        ));
+    }
+
+    (
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (header::CONTENT_DISPOSITION, "attachment; filename=\"bmp280_data.txt\""),
+        ],
+        csv,
+    )
+        .into_response()
+}
+
+// Health check para orquestradores: 200 se o broker entregou alguma mensagem recentemente, 503 caso contrário
+async fn handler_healthz(State(state): State<AppState>) -> (StatusCode, &'static str) {
+    let recente = travar(&state.ultima_mensagem).is_some_and(|t| t.elapsed() <= state.healthz_max);
+
+    if recente {
+        (StatusCode::OK, "ok")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "stale")
+    }
+}
+
+// Formato de exposição em texto do Prometheus. Usa o histórico ao vivo, mesmo com freeze ativo.
+async fn handler_metrics(State(state): State<AppState>) -> Response {
+    let mut ultimas: Vec<(String, SensorData)> = travar(&state.historico)
+        .iter()
+        .filter_map(|(id, history)| Some((id.clone(), history.back()?.dados)))
+        .collect();
+    ultimas.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut corpo = String::new();
+    let gauges = [
+        ("weather_temperature_celsius", "Última temperatura recebida", Campo::Temperatura),
+        ("weather_humidity_percent", "Última umidade relativa recebida", Campo::Umidade),
+        ("weather_pressure_hpa", "Última pressão recebida", Campo::Pressao),
+    ];
+    for (nome, ajuda, campo) in gauges {
+        corpo.push_str(&format!("# HELP {} {}\n# TYPE {} gauge\n", nome, ajuda, nome));
+        // Dispositivos sem a grandeza ficam fora da série
+        for (id, valor) in ultimas.iter().filter_map(|(id, dados)| Some((id, campo.valor(dados)?))) {
+            corpo.push_str(&format!("{}{{device=\"{}\"}} {}\n", nome, escapar_rotulo(id), valor));
+        }
+    }
+
+    let contadores = [
+        ("weather_readings_total", "Leituras aceitas desde a inicialização", &state.leituras_total),
+        ("weather_readings_discarded_total", "Leituras rejeitadas pela validação", &state.descartadas),
+    ];
+    for (nome, ajuda, contador) in contadores {
+        corpo.push_str(&format!(
+            "# HELP {} {}\n# TYPE {} counter\n{} {}\n",
+            nome,
+            ajuda,
+            nome,
+            nome,
+            contador.load(Ordering::Relaxed)
+        ));
+    }
+
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], corpo).into_response()
+}
+
+async fn handler_ws(State(state): State<AppState>, ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(move |socket| transmitir_ao_vivo(socket, state))
+}
+
+// Mensagem do /ws: o formato do /api/current mais a origem
+#[derive(Serialize)]
+struct LeituraAoVivo<'a> {
+    dispositivo: &'a str,
+    #[serde(flatten)]
+    registro: RegistroApi,
+}
+
+// Envia cada nova leitura como JSON até o cliente desconectar
+async fn transmitir_ao_vivo(mut socket: WebSocket, state: AppState) {
+    let mut rx = state.ao_vivo.subscribe();
+    let mut encerrando = state.encerrando.clone();
+
+    loop {
+        let recebido = tokio::select! {
+            recebido = rx.recv() => recebido,
+            // O graceful shutdown espera todas as conexões terminarem
+            _ = encerrando.changed() => break,
+        };
+
+        let (dispositivo, registro) = match recebido {
+            Ok(leitura) => leitura,
+            Err(broadcast::error::RecvError::Lagged(perdidas)) => {
+                warn!("Cliente /ws atrasado, {} leituras puladas", perdidas);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+
+        // Com o histórico congelado a página não deve mudar
+        if travar(&state.congelado).is_some() {
+            continue;
+        }
+
+        let leitura = LeituraAoVivo {
+            dispositivo: &dispositivo,
+            registro: RegistroApi::from(&registro),
+        };
+        let json = serde_json::to_string(&leitura).unwrap();
+        if socket.send(Message::Text(json)).await.is_err() {
+            break;
+        }
+    }
+}
+
+async fn handler_chart_png(
+    State(state): State<AppState>,
+    Query(dispositivo): Query<DispositivoParams>,
+    Query(decimacao): Query<DecimacaoParams>,
+) -> Response {
+    let registros: Vec<Registro> = state.historico_visivel(dispositivo.id()).iter().cloned().collect();
+    let registros = match decimacao.decimacao() {
+        Some(modo) => decimar(&registros, modo, |reg| reg.dados.temperatura),
+        None => registros,
+    };
+
+    match render_grafico_png(&registros) {
+        Ok(png) => ([(header::CONTENT_TYPE, "image/png")], png).into_response(),
+        Err(e) => {
+            error!("Erro ao gerar gráfico: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, e).into_response()
+        }
+    }
+}
 
-// Dados brutos que vêm do sensor
-#[derive(Deserialize, Debug, Clone, Copy)]
-struct SensorData {
-    temperatura: f64,
-    umidade: f64,
-    pressao: f64,
+// Gráfico de linha da temperatura; com histórico vazio gera uma imagem "Sem dados"
+fn render_grafico_png(history: &[Registro]) -> Result<Vec<u8>, String> {
+    const LARGURA: u32 = 800;
+    const ALTURA: u32 = 400;
+
+    let mut rgb = vec![0u8; (LARGURA * ALTURA * 3) as usize];
+    {
+        let root = BitMapBackend::with_buffer(&mut rgb, (LARGURA, ALTURA)).into_drawing_area();
+        root.fill(&WHITE).map_err(|e| e.to_string())?;
+
+        if history.is_empty() {
+            let estilo = ("sans-serif", 32).into_font().color(&RGBColor(136, 136, 136));
+            root.draw(&Text::new("Sem dados", (LARGURA as i32 / 2 - 80, ALTURA as i32 / 2 - 16), estilo))
+                .map_err(|e| e.to_string())?;
+        } else {
+            let temperaturas: Vec<f64> = history.iter().map(|r| r.dados.temperatura).collect();
+            let min = temperaturas.iter().cloned().fold(f64::INFINITY, f64::min);
+            let max = temperaturas.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+            // Margem para a linha não colar nas bordas (e eixo válido com valores iguais)
+            let margem = ((max - min) * 0.1).max(0.5);
+            let ultimo = history.len().max(2) - 1;
+
+            let mut chart = ChartBuilder::on(&root)
+                .caption("Temperatura (°C)", ("sans-serif", 24))
+                .margin(15)
+                .margin_right(40)
+                .x_label_area_size(40)
+                .y_label_area_size(50)
+                .build_cartesian_2d(0..ultimo, (min - margem)..(max + margem))
+                .map_err(|e| e.to_string())?;
+
+            chart
+                .configure_mesh()
+                .x_labels(history.len())
+                .x_label_formatter(&|i| history.get(*i).map(|r| r.horario.format("%H:%M:%S").to_string()).unwrap_or_default())
+                .x_desc("Horário")
+                .y_desc("°C")
+                .draw()
+                .map_err(|e| e.to_string())?;
+
+            chart
+                .draw_series(LineSeries::new(
+                    temperaturas.iter().enumerate().map(|(i, t)| (i, *t)),
+                    &RGBColor(231, 76, 60),
+                ))
+                .map_err(|e| e.to_string())?;
+        }
+
+        root.present().map_err(|e| e.to_string())?;
+    }
+
+    let imagem = image::RgbImage::from_raw(LARGURA, ALTURA, rgb).ok_or("Buffer de imagem inválido")?;
+    let mut png = Vec::new();
+    imagem
+        .write_to(&mut std::io::Cursor::new(&mut png), image::ImageOutputFormat::Png)
+        .map_err(|e| e.to_string())?;
+
+    Ok(png)
 }
 
-// Estrutura interna para guardar o dado + a hora que ele chegou
-#[derive(Debug, Clone)]
-struct Registro {
-    dados: SensorData,
-    horario: String,
+async fn handler_current(
+    State(state): State<AppState>,
+    Query(dispositivo): Query<DispositivoParams>,
+    Query(pressao): Query<PressaoParams>,
+    Query(idioma): Query<IdiomaParams>,
+    Query(formato): Query<FormatoParams>,
+) -> Response {
+    match state.historico_visivel(dispositivo.id()).back() {
+        Some(reg) => {
+            let registro = RegistroApi::from(reg).com_pressao_em(pressao.unidade());
+            if idioma.ingles() {
+                JsonFormatado(RegistroApiEn::from(registro), formato.pretty()).into_response()
+            } else {
+                JsonFormatado(registro, formato.pretty()).into_response()
+            }
+        }
+        // Sem leituras ainda: nada de zeros inventados
+        None => StatusCode::NO_CONTENT.into_response(),
+    }
 }
 
-// O estado é uma LISTA (Vector) de registros protegida por Mutex
-type SharedState = Arc<Mutex<Vec<Registro>>>;
+// Ordem cronológica: índice 0 é a leitura mais antiga, a última é a mais recente
+async fn handler_history(
+    State(state): State<AppState>,
+    Query(dispositivo): Query<DispositivoParams>,
+    Query(filtro): Query<HistoricoParams>,
+    Query(pressao): Query<PressaoParams>,
+    Query(idioma): Query<IdiomaParams>,
+    Query(formato): Query<FormatoParams>,
+) -> Response {
+    let historico = state.historico_visivel(dispositivo.id());
+    let unidade = pressao.unidade();
+    // Para polling incremental: nada mais novo que `since` dá um array vazio
+    let registros = historico
+        .iter()
+        .filter(|reg| filtro.since.is_none_or(|since| reg.horario > since))
+        .map(|reg| RegistroApi::from(reg).com_pressao_em(unidade));
 
-#[tokio::main]
-async fn main() {
-    // 1. Inicializa o Estado
-    let estado_compartilhado = Arc::new(Mutex::new(Vec::new()));
+    if idioma.ingles() {
+        JsonFormatado(registros.map(RegistroApiEn::from).collect::<Vec<_>>(), formato.pretty()).into_response()
+    } else {
+        JsonFormatado(registros.collect::<Vec<_>>(), formato.pretty()).into_response()
+    }
+}
 
-    // 2. Configuração MQTT para a Nuvem (Render)
-    // Usamos o broker público para permitir conexão externa
-    let mut mqttoptions = MqttOptions::new("rust-render-client", "test.mosquitto.org", 1883);
-    mqttoptions.set_keep_alive(Duration::from_secs(5));
+// Ids dos dispositivos com histórico, em ordem alfabética
+async fn handler_devices(
+    State(state): State<AppState>,
+    Query(formato): Query<FormatoParams>,
+) -> JsonFormatado<Vec<String>> {
+    let mut ids: Vec<String> = state.historicos_visiveis().into_keys().collect();
+    ids.sort();
+    JsonFormatado(ids, formato.pretty())
+}
 
-    let (client, mut eventloop) = AsyncClient::new(mqttoptions, 10);
+// Mín/máx/média da janela atual do histórico (204 sem leituras)
+async fn handler_stats(
+    State(state): State<AppState>,
+    Query(dispositivo): Query<DispositivoParams>,
+    Query(pressao): Query<PressaoParams>,
+    Query(formato): Query<FormatoParams>,
+) -> Response {
+    let mut historico = state.historico_visivel(dispositivo.id());
+    if pressao.nivel_mar() {
+        historico = historico_nivel_mar(&historico);
+    }
+    match resumo_historico(dispositivo.id(), &historico) {
+        Some(resumo) => JsonFormatado(resumo.com_pressao_em(pressao.unidade()), formato.pretty()).into_response(),
+        None => StatusCode::NO_CONTENT.into_response(),
+    }
+}
 
-    // Tópico Único e Exclusivo
-    client
-        .subscribe("sensores/rusty_weather/unb_211068459", QoS::AtLeastOnce)
-        .await
-        .unwrap();
+async fn handler_config(
+    State(state): State<AppState>,
+    Query(formato): Query<FormatoParams>,
+) -> JsonFormatado<ConfigPublica> {
+    let config = ConfigPublica {
+        faixas: state.faixas,
+        capacidade_historico: state.capacidade,
+        leituras_descartadas: state.descartadas.load(Ordering::Relaxed),
+    };
 
-    // 3. Loop MQTT (Processamento em Background)
-    // IMPORTANTE: O tokio::spawn deve estar DENTRO da main, antes do servidor web travar o processo.
-    let estado_para_mqtt = estado_compartilhado.clone();
-    
-    tokio::spawn(async move {
-        loop {
-            match eventloop.poll().await {
-                Ok(notification) => {
-                    if let Event::Incoming(Packet::Publish(p)) = notification {
-                        if let Ok(dados_sensor) = serde_json::from_slice::<SensorData>(&p.payload) {
-                            println!("Recebido: {:?}", dados_sensor);
-                            
-                            let agora = Local::now().format("%H:%M:%S").to_string();
-                            
-                            let novo_registro = Registro {
-                                dados: dados_sensor,
-                                horario: agora,
-                            };
-
-                            let mut history = estado_para_mqtt.lock().unwrap();
-                            history.push(novo_registro);
-
-                            // Mantém apenas os últimos 10 registros
-                            if history.len() > 10 {
-                                history.remove(0);
-                            }
-                        }
-                    }
-                }
-                Err(e) => {
-                    println!("Erro MQTT: {:?}", e);
-                    tokio::time::sleep(Duration::from_secs(1)).await;
+    JsonFormatado(config, formato.pretty())
+}
+
+// Agrega o histórico completo gravado no SQLite, não só as leituras em memória
+async fn handler_aggregate(
+    State(state): State<AppState>,
+    Query(params): Query<AgregacaoParams>,
+    Query(dispositivo): Query<DispositivoParams>,
+    Query(formato): Query<FormatoParams>,
+) -> Response {
+    let Some(banco) = &state.banco else {
+        return (StatusCode::SERVICE_UNAVAILABLE, "Persistência desativada").into_response();
+    };
+
+    match agregar_leituras(&travar(banco), dispositivo.id(), params.metric, params.bucket) {
+        Ok(intervalos) => JsonFormatado(intervalos, formato.pretty()).into_response(),
+        Err(e) => {
+            error!("Erro ao agregar leituras: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Erro ao consultar o banco").into_response()
+        }
+    }
+}
+
+async fn handler_series(
+    State(state): State<AppState>,
+    Query(params): Query<SeriesParams>,
+    Query(decimacao): Query<DecimacaoParams>,
+    Query(dispositivo): Query<DispositivoParams>,
+    Query(formato): Query<FormatoParams>,
+) -> JsonFormatado<Vec<PontoSerie>> {
+    let pontos: Vec<PontoSerie> = state
+        .historico_visivel(dispositivo.id())
+        .iter()
+        // Leituras sem a grandeza pedida não entram na série
+        .filter_map(|reg| {
+            Some(PontoSerie {
+                horario: reg.horario.format("%H:%M:%S").to_string(),
+                valor: params.campo.valor(&reg.dados)?,
+            })
+        })
+        .collect();
+
+    match decimacao.decimacao() {
+        Some(modo) => JsonFormatado(decimar(&pontos, modo, |p| p.valor), formato.pretty()),
+        None => JsonFormatado(pontos, formato.pretty()),
+    }
+}
+
+async fn handler_divergencia(
+    State(state): State<AppState>,
+    Query(dispositivo): Query<DispositivoParams>,
+    Query(formato): Query<FormatoParams>,
+) -> Result<JsonFormatado<Divergencia>, (StatusCode, String)> {
+    let history = state.historico_visivel(dispositivo.id());
+
+    history
+        .back()
+        .and_then(|reg| Divergencia::calcular(&reg.dados))
+        .map(|d| JsonFormatado(d, formato.pretty()))
+        .ok_or((
+            StatusCode::NOT_FOUND,
+            "Nenhuma leitura com as duas temperaturas".to_string(),
+        ))
+}
+
+async fn handler_import(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(dispositivo): Query<DispositivoParams>,
+    Query(formato): Query<FormatoParams>,
+    corpo: String,
+) -> Result<JsonFormatado<ResultadoImportacao>, (StatusCode, String)> {
+    autorizar(&headers, ENV_ADMIN_TOKEN)?;
+    let id = dispositivo.id_para_gravar()?;
+    verificar_limite_dispositivos(&travar(&state.historico), id)?;
+
+    let mut resultado = ResultadoImportacao::default();
+    let mut novos = Vec::new();
+
+    for linha in corpo.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        // Cabeçalho do export
+        if linha.starts_with("horario") {
+            continue;
+        }
+
+        match parse_linha_csv(linha) {
+            Some(registro) if state.faixas.validar(&registro.dados).is_ok() => novos.push(registro),
+            Some(_) => {
+                state.descartadas.fetch_add(1, Ordering::Relaxed);
+                resultado.ignorados += 1;
+            }
+            None => resultado.ignorados += 1,
+        }
+    }
+
+    resultado.importados = novos.len();
+
+    let mut historicos = travar(&state.historico);
+    // Outro dispositivo pode ter ocupado a última vaga durante o parse
+    verificar_limite_dispositivos(&historicos, id)?;
+    let history = Arc::make_mut(historicos.entry(id.to_string()).or_default());
+    history.extend(novos);
+    // Ordenação estável: em instantes iguais, os registros existentes vêm primeiro
+    history.make_contiguous().sort_by_key(|reg| reg.horario);
+
+    resultado.descartados_por_capacidade =
+        aplicar_retencao(history, state.capacidade, state.idade_maxima, Local::now());
+
+    info!("Importação CSV: {:?}", resultado);
+    Ok(JsonFormatado(resultado, formato.pretty()))
+}
+
+// Existe leitura do dispositivo com a grandeza a até INGESTAO_TOLERANCIA_SEGUNDOS de `horario`
+fn leitura_gravada(conn: &Connection, dispositivo: &str, campo: Campo, horario: DateTime<Local>) -> rusqlite::Result<bool> {
+    let tolerancia = TimeDelta::seconds(INGESTAO_TOLERANCIA_SEGUNDOS);
+    // `coluna()` é fixa por variante, então interpolar no SQL é seguro
+    let mut stmt = conn.prepare_cached(&format!(
+        "SELECT EXISTS(SELECT 1 FROM readings
+         WHERE COALESCE(dispositivo, ?1) = ?2 AND ts BETWEEN ?3 AND ?4 AND {} IS NOT NULL)",
+        campo.coluna()
+    ))?;
+    stmt.query_row(
+        (
+            DISPOSITIVO_PADRAO,
+            dispositivo,
+            (horario - tolerancia).to_rfc3339(),
+            (horario + tolerancia).to_rfc3339(),
+        ),
+        |row| row.get(0),
+    )
+}
+
+// Backfill com os arquivos do SPIFFS de uma estação que ficou offline. Aceita o corpo em
+// gzip (Content-Encoding). Reenviar o mesmo arquivo é seguro: o que já existe vira duplicata.
+async fn handler_ingest(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(dispositivo): Query<DispositivoParams>,
+    Query(params): Query<IngestaoParams>,
+    Query(formato): Query<FormatoParams>,
+    corpo: String,
+) -> Response {
+    let id = match autorizar(&headers, ENV_ADMIN_TOKEN)
+        .and_then(|()| dispositivo.id_para_gravar())
+        .and_then(|id| verificar_limite_dispositivos(&travar(&state.historico), id).map(|()| id))
+    {
+        Ok(id) => id,
+        Err(erro) => return erro.into_response(),
+    };
+
+    let mut resultado = ResultadoIngestao::default();
+    let campo = params.sensor.campo();
+    let tolerancia = TimeDelta::seconds(INGESTAO_TOLERANCIA_SEGUNDOS);
+    // O histórico ao vivo, mesmo com freeze ativo: é nele que as leituras vão entrar
+    let existente = travar(&state.historico).get(id).cloned().unwrap_or_default();
+
+    let mut novos = Vec::new();
+    for linha in corpo.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        let registro = match params.sensor.parse_linha(linha) {
+            Some(registro) if state.faixas.validar(&registro.dados).is_ok() => registro,
+            Some(_) => {
+                state.descartadas.fetch_add(1, Ordering::Relaxed);
+                resultado.ignorados += 1;
+                continue;
+            }
+            None => {
+                resultado.ignorados += 1;
+                continue;
+            }
+        };
+
+        let na_memoria = existente
+            .iter()
+            .any(|reg| campo.valor(&reg.dados).is_some() && (reg.horario - registro.horario).abs() <= tolerancia);
+        if na_memoria {
+            resultado.duplicados += 1;
+        } else {
+            novos.push(registro);
+        }
+    }
+
+    // Além do histórico em memória (só as últimas leituras), confere e grava no banco
+    if let Some(banco) = &state.banco {
+        let conn = travar(banco);
+        let gravados = (|| {
+            let mut gravados = Vec::new();
+            let tx = conn.unchecked_transaction()?;
+            for registro in novos.drain(..) {
+                if leitura_gravada(&tx, id, campo, registro.horario)? {
+                    resultado.duplicados += 1;
+                } else {
+                    persistir_leitura(&tx, id, &registro.dados, &registro.horario)?;
+                    gravados.push(registro);
                 }
             }
+            tx.commit()?;
+            Ok::<_, rusqlite::Error>(gravados)
+        })();
+
+        match gravados {
+            Ok(gravados) => novos = gravados,
+            Err(e) => {
+                error!("Erro ao gravar leituras recebidas: {}", e);
+                return (StatusCode::INTERNAL_SERVER_ERROR, "Erro ao gravar no banco").into_response();
+            }
         }
-    });
+    }
 
-    // 4. Configuração do Servidor Web
-    let app = Router::new()
-        .route("/", get(handler_dashboard))
-        .with_state(estado_compartilhado);
+    resultado.importados = novos.len();
 
-    // Configuração de Porta para o Render (0.0.0.0:3000)
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    println!("Servidor Web rodando na nuvem (Porta 3000)");
-    
-    axum::serve(listener, app).await.unwrap();
+    let mut historicos = travar(&state.historico);
+    let history = Arc::make_mut(historicos.entry(id.to_string()).or_default());
+    history.extend(novos);
+    history.make_contiguous().sort_by_key(|reg| reg.horario);
+    resultado.descartados_por_capacidade =
+        aplicar_retencao(history, state.capacidade, state.idade_maxima, Local::now());
+
+    info!("Ingestão de {} ({:?}): {:?}", id, params.sensor, resultado);
+    JsonFormatado(resultado, formato.pretty()).into_response()
 }
 
-async fn handler_dashboard(State(state): State<SharedState>) -> Html<String> {
-    let history = state.lock().unwrap();
+async fn handler_ota(
+    State(state): State<AppState>,
+    Query(formato): Query<FormatoParams>,
+    headers: HeaderMap,
+    Json(comando): Json<ComandoOta>,
+) -> Result<JsonFormatado<serde_json::Value>, (StatusCode, String)> {
+    autorizar(&headers, ENV_OTA_TOKEN)?;
 
-    let atual = history.last().cloned().unwrap_or(Registro {
-        dados: SensorData { temperatura: 0.0, umidade: 0.0, pressao: 0.0 },
-        horario: "--:--:--".to_string(),
-    });
+    comando
+        .validar()
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    info!("Comando OTA: url={} checksum={:?}", comando.url, comando.checksum);
+
+    let payload = serde_json::to_vec(&comando).unwrap();
+    state
+        .mqtt
+        .publish(TOPICO_OTA, QoS::AtLeastOnce, false, payload)
+        .await
+        .map_err(|e| (StatusCode::SERVICE_UNAVAILABLE, format!("Erro MQTT: {:?}", e)))?;
+
+    Ok(JsonFormatado(
+        serde_json::json!({ "status": "enviado", "topico": TOPICO_OTA }),
+        formato.pretty(),
+    ))
+}
 
+async fn handler_snapshot(
+    State(state): State<AppState>,
+    Query(dispositivo): Query<DispositivoParams>,
+    Query(formato): Query<FormatoParams>,
+) -> JsonFormatado<Snapshot> {
+    let snapshot = Snapshot {
+        schema_version: SCHEMA_VERSION,
+        historico: state.historico_visivel(dispositivo.id()).iter().cloned().collect(),
+    };
+
+    JsonFormatado(snapshot, formato.pretty())
+}
+
+async fn handler_restaurar_snapshot(
+    State(state): State<AppState>,
+    Query(dispositivo): Query<DispositivoParams>,
+    headers: HeaderMap,
+    Json(snapshot): Json<Snapshot>,
+) -> Result<String, (StatusCode, String)> {
+    autorizar(&headers, ENV_ADMIN_TOKEN)?;
+    let id = dispositivo.id_para_gravar()?;
+
+    let versao_original = snapshot.schema_version;
+    let snapshot = snapshot.migrar()?;
+
+    // Mesma retenção do histórico ao vivo: capacidade e HISTORY_MAX_AGE_MINUTES, que
+    // dependem da ordem cronológica (um snapshot editado à mão pode vir fora de ordem)
+    let mut historico = VecDeque::from(snapshot.historico);
+    historico.make_contiguous().sort_by_key(|reg| reg.horario);
+    aplicar_retencao(&mut historico, state.capacidade, state.idade_maxima, Local::now());
+    let restaurados = historico.len();
+
+    let mut historicos = travar(&state.historico);
+    verificar_limite_dispositivos(&historicos, id)?;
+    historicos.insert(id.to_string(), Arc::new(historico));
+    info!(
+        "Snapshot restaurado em {}: {} registros (schema v{} -> v{})",
+        id, restaurados, versao_original, SCHEMA_VERSION
+    );
+
+    Ok(format!("{} registros restaurados", restaurados))
+}
+
+async fn handler_freeze(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<&'static str, (StatusCode, String)> {
+    autorizar(&headers, ENV_ADMIN_TOKEN)?;
+
+    let mut congelado = travar(&state.congelado);
+    if congelado.is_none() {
+        *congelado = Some(travar(&state.historico).clone());
+        info!("Histórico congelado (ingestão continua)");
+    }
+
+    Ok("congelado")
+}
+
+async fn handler_unfreeze(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<&'static str, (StatusCode, String)> {
+    autorizar(&headers, ENV_ADMIN_TOKEN)?;
+
+    if travar(&state.congelado).take().is_some() {
+        info!("Histórico descongelado");
+    }
+
+    Ok("ao vivo")
+}
+
+// Resposta do POST /api/reset
+#[derive(Serialize, Debug)]
+struct ResultadoReset {
+    removidas: usize,
+}
+
+// Esvazia o histórico em memória de um dispositivo (?device=) ou de todos (sem o parâmetro).
+// O SQLite não é tocado: as leituras antigas voltam ao reiniciar o processo.
+async fn handler_reset(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(dispositivo): Query<DispositivoParams>,
+    Query(formato): Query<FormatoParams>,
+) -> Result<JsonFormatado<ResultadoReset>, (StatusCode, String)> {
+    autorizar(&headers, ENV_ADMIN_TOKEN)?;
+
+    let limpar = |historicos: &mut Historicos| -> usize {
+        match &dispositivo.device {
+            Some(id) => historicos.remove(id).map_or(0, |h| h.len()),
+            None => historicos.drain().map(|(_, h)| h.len()).sum(),
+        }
+    };
+
+    let removidas = limpar(&mut travar(&state.historico));
+    // Um histórico congelado também sumiria da tela; some junto
+    if let Some(congelado) = travar(&state.congelado).as_mut() {
+        limpar(congelado);
+    }
+
+    info!(
+        "Histórico em memória limpo ({}): {} leituras removidas",
+        dispositivo.device.as_deref().unwrap_or("todos os dispositivos"),
+        removidas
+    );
+    Ok(JsonFormatado(ResultadoReset { removidas }, formato.pretty()))
+}
+
+fn render_tabela(id: &str, linhas: &[LinhaHistorico], rotulos: &Rotulos) -> String {
     let mut linhas_tabela = String::new();
-    for reg in history.iter().rev() {
+    for linha in linhas {
         linhas_tabela.push_str(&format!(
             "<tr>
+                <td title=\"{}\">{}</td>
+                <td>{}</td>
+                <td>{}</td>
                 <td>{}</td>
-                <td>{:.1} °C</td>
-                <td>{:.1} %</td>
-                <td>{:.1} hPa</td>
             </tr>",
-            reg.horario, reg.dados.temperatura, reg.dados.umidade, reg.dados.pressao
+            linha.data_hora, linha.horario, linha.temperatura, linha.umidade, linha.pressao
         ));
     }
 
-    let html = format!(
-        r#"
-        <!DOCTYPE html>
-        <html>
-        <head>
-            <title>Rusty Weather Station</title>
-            <meta http-equiv="refresh" content="3">
-            <style>
-                body {{ font-family: sans-serif; background: #f4f4f9; padding: 20px; text-align: center; }}
-                .cards {{ display: flex; justify-content: center; gap: 20px; margin-bottom: 40px; }}
-                .card {{ background: white; padding: 20px; border-radius: 10px; box-shadow: 0 2px 5px rgba(0,0,0,0.1); width: 180px; }}
-                .val {{ font-size: 2.5rem; font-weight: bold; margin: 10px 0; }}
-                .ts {{ color: #888; margin-bottom: 20px; }}
-                
-                table {{ margin: 0 auto; border-collapse: collapse; width: 80%; max-width: 600px; background: white; }}
-                th, td {{ padding: 12px; border-bottom: 1px solid #ddd; text-align: center; }}
-                th {{ background-color: #333; color: white; }}
-                tr:nth-child(even) {{ background-color: #f9f9f9; }}
-            </style>
-        </head>
-        <body>
-            <h1>Rusty Weather Dashboard 🦀</h1>
-            <div class="ts">Última atualização: <strong>{}</strong></div>
-
-            <div class="cards">
-                <div class="card"><div style="color: #e74c3c">Temp</div><div class="val">{:.1}</div><div>°C</div></div>
-                <div class="card"><div style="color: #3498db">Umid</div><div class="val">{:.1}</div><div>%</div></div>
-                <div class="card"><div style="color: #2ecc71">Press</div><div class="val">{:.1}</div><div>hPa</div></div>
-            </div>
-
-            <h3>Histórico Recente (Últimas 10 leituras)</h3>
-            <table>
+    format!(
+        r#"<div class="tabela-rolagem"><table>
                 <thead>
                     <tr>
                         <th>Horário</th>
-                        <th>Temp</th>
-                        <th>Umidade</th>
-                        <th>Pressão</th>
+                        <th>{}</th>
+                        <th>{}</th>
+                        <th>{}</th>
                     </tr>
                 </thead>
-                <tbody>
+                <tbody id="historico-{}">
                     {}
                 </tbody>
-            </table>
-        </body>
-        </html>
-        "#,
-        atual.horario,
-        atual.dados.temperatura,
-        atual.dados.umidade,
-        atual.dados.pressao,
+            </table></div>"#,
+        rotulos.temperatura.completo,
+        rotulos.umidade.completo,
+        rotulos.pressao.completo,
+        id,
         linhas_tabela
-    );
+    )
+}
 
-    Html(html)
+fn render_lista(id: &str, linhas: &[LinhaHistorico]) -> String {
+    let mut itens = String::new();
+    for linha in linhas {
+        itens.push_str(&format!(
+            "<li title=\"{}\">{} — {} / {} / {}</li>",
+            linha.data_hora, linha.horario, linha.temperatura, linha.umidade, linha.pressao
+        ));
+    }
+
+    format!(r#"<ul class="lista" id="historico-{}">{}</ul>"#, id, itens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    // Compara com tolerância; as referências são tabelas publicadas, não a própria fórmula
+    fn assert_proximo(obtido: f64, esperado: f64, tolerancia: f64, caso: &str) {
+        assert!(
+            (obtido - esperado).abs() <= tolerancia,
+            "{}: obtido {}, esperado {} ± {}",
+            caso,
+            obtido,
+            esperado,
+            tolerancia
+        );
+    }
+
+    #[test]
+    fn decimar_passo_mantem_um_a_cada_k() {
+        let pontos: Vec<f64> = (0..10).map(f64::from).collect();
+        let casos: &[(usize, &[f64])] = &[
+            (1, &[0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0]),
+            (3, &[0.0, 3.0, 6.0, 9.0]),
+            (4, &[0.0, 4.0, 8.0]),
+            // Passo 0 é tratado como 1 em vez de entrar em panic no step_by
+            (0, &[0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0]),
+        ];
+
+        for (k, esperado) in casos {
+            assert_eq!(decimar(&pontos, Decimacao::Passo(*k), |v| *v), *esperado, "passo {}", k);
+        }
+    }
+
+    #[test]
+    fn decimar_minmax_preserva_extremos_em_ordem() {
+        let pontos = [1.0, 5.0, 2.0, 3.0, -4.0, 0.0, 7.0, 6.0, 2.0, 1.0, 9.0, 8.0];
+
+        // 12 pontos em 3 colunas de 4: mínimo e máximo de cada uma, na ordem original
+        assert_eq!(
+            decimar(&pontos, Decimacao::MinMax(3), |v| *v),
+            [1.0, 5.0, -4.0, 7.0, 1.0, 9.0]
+        );
+    }
+
+    #[test]
+    fn decimar_minmax_nao_reduz_series_curtas() {
+        let pontos = [3.0, 1.0, 2.0, 4.0];
+
+        assert_eq!(decimar(&pontos, Decimacao::MinMax(2), |v| *v), pontos);
+        assert_eq!(decimar(&pontos, Decimacao::MinMax(0), |v| *v), pontos);
+    }
+
+    #[test]
+    fn decimar_minmax_bloco_constante_gera_um_ponto() {
+        let pontos = [2.0; 8];
+
+        assert_eq!(decimar(&pontos, Decimacao::MinMax(2), |v| *v), [2.0, 2.0]);
+    }
+
+    #[test]
+    fn dew_point_valores_de_referencia() {
+        // (temperatura °C, umidade %, ponto de orvalho °C)
+        let casos = [
+            (25.0, 60.0, 16.7),
+            (20.0, 100.0, 20.0),
+            (10.0, 50.0, 0.1),
+            (30.0, 80.0, 26.2),
+            (0.0, 70.0, -4.8),
+        ];
+
+        for (temp, rh, esperado) in casos {
+            assert_proximo(dew_point(temp, rh), esperado, 0.1, &format!("{} °C / {} %", temp, rh));
+        }
+    }
+
+    #[test]
+    fn dew_point_umidade_zero_e_finito() {
+        let orvalho = dew_point(30.0, 0.0);
+
+        assert!(orvalho.is_finite());
+        assert!(orvalho < -40.0);
+    }
+
+    #[test]
+    fn heat_index_valores_de_referencia() {
+        // Tabela do NOAA em °F convertida para °C: 86 °F/50 % → 88 °F, 90 °F/70 % → 106 °F,
+        // 95 °F/60 % → 113 °F
+        let casos = [
+            (30.0, 50.0, 31.1),
+            (32.2, 70.0, 41.1),
+            (35.0, 60.0, 45.0),
+        ];
+
+        for (temp, rh, esperado) in casos {
+            assert_proximo(heat_index(temp, rh), esperado, 0.6, &format!("{} °C / {} %", temp, rh));
+        }
+    }
+
+    #[test]
+    fn heat_index_abaixo_do_limite_devolve_temperatura() {
+        for (temp, rh) in [(20.0, 80.0), (26.9, 95.0), (-5.0, 50.0)] {
+            assert_eq!(heat_index(temp, rh), temp);
+        }
+    }
+
+    #[test]
+    fn absolute_humidity_valores_de_referencia() {
+        // (temperatura °C, umidade %, umidade absoluta g/m³)
+        let casos = [
+            (20.0, 50.0, 8.6),
+            (30.0, 80.0, 24.3),
+            (0.0, 100.0, 4.85),
+            (-10.0, 100.0, 2.36),
+            (25.0, 0.0, 0.0),
+        ];
+
+        for (temp, rh, esperado) in casos {
+            assert_proximo(absolute_humidity(temp, rh), esperado, 0.1, &format!("{} °C / {} %", temp, rh));
+        }
+    }
+
+    #[test]
+    fn absolute_humidity_fora_da_faixa_e_limitada() {
+        // Sem o limite de Magnus, -300 °C daria temperatura absoluta negativa
+        let frio = absolute_humidity(-300.0, 50.0);
+        assert!(frio.is_finite() && frio >= 0.0);
+        assert_eq!(frio, absolute_humidity(MAGNUS_FAIXA_C.0, 50.0));
+
+        assert_eq!(absolute_humidity(40.0, 150.0), absolute_humidity(40.0, 100.0));
+    }
+
+    const UNIDADES_PRESSAO: [UnidadePressao; 3] = [UnidadePressao::Hpa, UnidadePressao::InHg, UnidadePressao::MmHg];
+
+    #[test]
+    fn pressao_atmosfera_padrao_em_cada_unidade() {
+        // 1 atm = 1013,25 hPa = 29,92 inHg = 760 mmHg
+        let casos = [
+            (UnidadePressao::Hpa, 1013.25, "1013.2"),
+            (UnidadePressao::InHg, 29.92, "29.92"),
+            (UnidadePressao::MmHg, 760.0, "760.0"),
+        ];
+
+        for (unidade, esperado, texto) in casos {
+            assert_proximo(unidade.converter(1013.25), esperado, 0.01, unidade.simbolo());
+            assert_eq!(unidade.formatar(1013.25), texto);
+        }
+    }
+
+    #[test]
+    fn pressao_ida_e_volta_preserva_hpa() {
+        for unidade in UNIDADES_PRESSAO {
+            for hpa in [300.0, 987.6, 1013.25, 1100.0] {
+                assert_proximo(unidade.converter(hpa) / unidade.fator(), hpa, 1e-9, unidade.simbolo());
+            }
+        }
+    }
+
+    #[test]
+    fn pressao_parametro_volta_para_a_mesma_unidade() {
+        for unidade in UNIDADES_PRESSAO {
+            for texto in [unidade.parametro().to_string(), unidade.parametro().to_uppercase()] {
+                let params = PressaoParams { punit: Some(texto), msl: None };
+                assert_eq!(params.unidade(), unidade);
+            }
+        }
+
+        // Texto livre: o desconhecido cai em hPa
+        let params = PressaoParams { punit: Some("psi".to_string()), msl: None };
+        assert_eq!(params.unidade(), UnidadePressao::Hpa);
+    }
+
+    #[test]
+    fn to_sea_level_atmosfera_padrao() {
+        // Atmosfera padrão (ISA): (altitude m, temperatura °C, pressão da estação hPa) → 1013,25 hPa
+        let casos = [
+            (0.0, 15.0, 1013.25),
+            (500.0, 11.75, 954.61),
+            (1000.0, 8.5, 898.76),
+            (1500.0, 5.25, 845.59),
+        ];
+
+        for (altitude, temp, estacao) in casos {
+            assert_proximo(to_sea_level(estacao, altitude, temp), 1013.25, 0.1, &format!("{} m", altitude));
+        }
+    }
+
+    #[test]
+    fn to_sea_level_abaixo_da_referencia_reduz_a_pressao() {
+        let nivel_mar = to_sea_level(1025.3, -100.0, 20.0);
+
+        assert!(nivel_mar < 1025.3);
+        assert_proximo(nivel_mar, 1013.4, 0.1, "-100 m");
+    }
+
+    // Leitura `minuto` minutos depois de um instante fixo, só com temperatura, pressão e altitude
+    fn leitura(minuto: i64, pressao: Option<f64>, altitude: Option<f64>) -> Registro {
+        Registro {
+            dados: SensorData {
+                temperatura: 15.0,
+                umidade: None,
+                pressao,
+                temperatura_dht11: None,
+                bateria: None,
+                altitude,
+            },
+            horario: Local.with_ymd_and_hms(2026, 1, 1, 12, 0, 0).unwrap() + TimeDelta::minutes(minuto),
+        }
+    }
+
+    #[test]
+    fn resumo_historico_tendencia_e_confianca() {
+        let history: VecDeque<Registro> = [
+            leitura(0, Some(1010.0), None),
+            leitura(1, None, None),
+            leitura(2, Some(1010.5), None),
+            leitura(3, Some(1011.5), None),
+        ]
+        .into();
+
+        let resumo = resumo_historico("esp32", &history).unwrap();
+
+        assert_eq!(resumo.amostras, 4);
+        assert_eq!((resumo.inicio.as_str(), resumo.fim.as_str()), ("12:00:00", "12:03:00"));
+        // Leituras sem pressão ficam fora da variação e do desvio
+        assert_proximo(resumo.variacao_pressao.unwrap(), 1.5, 1e-9, "variação");
+        assert_eq!(resumo.tendencia_pressao, Some(TendenciaPressao::Subindo));
+        assert_proximo(resumo.desvio_pressao.unwrap(), 0.764, 0.001, "desvio");
+        assert_eq!(resumo.confianca_tendencia, Some(ConfiancaTendencia::Volatil));
+    }
+
+    #[test]
+    fn resumo_historico_sem_pressao_suficiente() {
+        let history: VecDeque<Registro> = [leitura(0, Some(1010.0), None), leitura(1, None, None)].into();
+
+        let resumo = resumo_historico("esp32", &history).unwrap();
+
+        assert_eq!(resumo.variacao_pressao, None);
+        assert_eq!(resumo.tendencia_pressao, None);
+        assert_eq!(resumo.desvio_pressao, None);
+        assert!(resumo_historico("esp32", &VecDeque::new()).is_none());
+    }
+
+    #[test]
+    fn historico_nivel_mar_so_corrige_leituras_com_altitude() {
+        // 1000 m da atmosfera padrão, onde a temperatura é 8,5 °C
+        let mut alta = leitura(0, Some(898.76), Some(1000.0));
+        alta.dados.temperatura = 8.5;
+        let history: VecDeque<Registro> = [alta, leitura(1, Some(1000.0), None)].into();
+
+        let corrigido = historico_nivel_mar(&history);
+
+        assert_proximo(corrigido[0].dados.pressao.unwrap(), 1013.25, 0.1, "com altitude");
+        assert_eq!(corrigido[1].dados.pressao, Some(1000.0));
+        // O histórico original não muda
+        assert_eq!(history[0].dados.pressao, Some(898.76));
+    }
+
+    #[test]
+    fn snapshot_v1_migra_para_a_versao_atual() {
+        // v1: sem schema_version, umidade e pressão obrigatórias, horario só com a hora
+        let json = r#"{"historico": [
+            {"dados": {"temperatura": 24.5, "umidade": 55.0, "pressao": 1012.3}, "horario": "12:34:56"}
+        ]}"#;
+
+        let snapshot = serde_json::from_str::<Snapshot>(json).unwrap().migrar().unwrap();
+
+        assert_eq!(snapshot.schema_version, SCHEMA_VERSION);
+        let reg = &snapshot.historico[0];
+        assert_eq!(reg.dados.temperatura, 24.5);
+        assert_eq!(reg.dados.umidade, Some(55.0));
+        assert_eq!(reg.dados.pressao, Some(1012.3));
+        // Campos criados depois da v1 ficam ausentes
+        assert_eq!(reg.dados.temperatura_dht11, None);
+        assert_eq!(reg.dados.bateria, None);
+        assert_eq!(reg.dados.altitude, None);
+        // Só a hora: a data é a de hoje
+        assert_eq!(reg.horario.format("%H:%M:%S").to_string(), "12:34:56");
+        assert_eq!(reg.horario.date_naive(), Local::now().date_naive());
+    }
+
+    #[test]
+    fn snapshot_da_versao_atual_volta_igual() {
+        let mut registro = leitura(0, Some(1010.0), Some(800.0));
+        registro.dados.umidade = Some(40.0);
+        registro.dados.temperatura_dht11 = Some(15.5);
+        registro.dados.bateria = Some(3.9);
+        let original = Snapshot { schema_version: SCHEMA_VERSION, historico: vec![registro.clone()] };
+
+        let json = serde_json::to_string(&original).unwrap();
+        let snapshot = serde_json::from_str::<Snapshot>(&json).unwrap().migrar().unwrap();
+
+        assert_eq!(snapshot.schema_version, SCHEMA_VERSION);
+        let reg = &snapshot.historico[0];
+        assert_eq!(reg.horario, registro.horario);
+        assert_eq!(
+            (reg.dados.temperatura, reg.dados.umidade, reg.dados.pressao),
+            (15.0, Some(40.0), Some(1010.0))
+        );
+        assert_eq!(
+            (reg.dados.temperatura_dht11, reg.dados.bateria, reg.dados.altitude),
+            (Some(15.5), Some(3.9), Some(800.0))
+        );
+    }
+
+    #[test]
+    fn snapshot_de_versao_futura_e_recusado_com_422() {
+        for versao in [0, SCHEMA_VERSION + 1] {
+            let snapshot = Snapshot { schema_version: versao, historico: Vec::new() };
+
+            let (status, _) = snapshot.migrar().unwrap_err();
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "v{}", versao);
+        }
+    }
+
+    #[test]
+    fn dispositivo_do_heartbeat_extrai_o_id() {
+        assert_eq!(dispositivo_do_heartbeat("sensores/rusty_weather/esp32/heartbeat"), Some("esp32"));
+        assert_eq!(dispositivo_do_heartbeat("sensores/rusty_weather/sala-2/heartbeat"), Some("sala-2"));
+        // Tópico antigo, sem id, e níveis extras ou inválidos
+        assert_eq!(dispositivo_do_heartbeat("sensores/esp32/heartbeat"), None);
+        assert_eq!(dispositivo_do_heartbeat("sensores/rusty_weather/esp32"), None);
+        assert_eq!(dispositivo_do_heartbeat("sensores/rusty_weather//heartbeat"), None);
+        assert_eq!(dispositivo_do_heartbeat("sensores/rusty_weather/a/b/heartbeat"), None);
+    }
+
+    #[test]
+    fn parse_linha_bmp280() {
+        let reg = FonteIngestao::Bmp280.parse_linha("1767225600,23.45,1008.12,43.2").unwrap();
+        assert_eq!(reg.horario.timestamp(), 1_767_225_600);
+        assert_eq!(reg.dados.temperatura, 23.45);
+        assert_eq!(reg.dados.pressao, Some(1008.12));
+        assert_eq!(reg.dados.altitude, Some(43.2));
+        assert_eq!(reg.dados.umidade, None);
+    }
+
+    #[test]
+    fn parse_linha_dht11() {
+        // Espaços em volta dos campos são aceitos
+        let reg = FonteIngestao::Dht11.parse_linha(" 1767225600, 24.0, 61.0 ").unwrap();
+        assert_eq!(reg.horario.timestamp(), 1_767_225_600);
+        assert_eq!(reg.dados.temperatura, 24.0);
+        assert_eq!(reg.dados.umidade, Some(61.0));
+        assert_eq!(reg.dados.pressao, None);
+        assert_eq!(reg.dados.altitude, None);
+    }
+
+    #[test]
+    fn parse_linha_rejeita_malformadas() {
+        let casos = [
+            (FonteIngestao::Bmp280, ""),
+            (FonteIngestao::Bmp280, "1767225600"),
+            (FonteIngestao::Bmp280, "1767225600,23.4,1008.1"),
+            (FonteIngestao::Bmp280, "1767225600,23.4,1008.1,43.2,9"),
+            (FonteIngestao::Bmp280, "1767225600,23.4,abc,43.2"),
+            (FonteIngestao::Bmp280, "agora,23.4,1008.1,43.2"),
+            // Layout do outro sensor
+            (FonteIngestao::Dht11, "1767225600,23.4,1008.1,43.2"),
+            (FonteIngestao::Dht11, "1767225600,23.4"),
+            (FonteIngestao::Dht11, "1767225600,,61.0"),
+        ];
+        for (fonte, linha) in casos {
+            assert!(fonte.parse_linha(linha).is_none(), "{:?} aceitou {:?}", fonte, linha);
+        }
+    }
+
+    #[test]
+    fn parse_linha_rejeita_uptime_antes_do_sntp() {
+        // Segundos desde o boot, gravados antes da sincronização do relógio
+        assert!(FonteIngestao::Dht11.parse_linha("120,24.0,61.0").is_none());
+        let limite = EPOCH_MINIMO_VALIDO;
+        assert!(FonteIngestao::Dht11.parse_linha(&format!("{},24.0,61.0", limite - 1)).is_none());
+        assert!(FonteIngestao::Dht11.parse_linha(&format!("{},24.0,61.0", limite)).is_some());
+    }
 }
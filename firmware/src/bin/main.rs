@@ -1,22 +1,294 @@
+use esp_idf_svc::eventloop::EspSystemEventLoop;
 use esp_idf_svc::hal::{
+    adc::{
+        attenuation::DB_11,
+        oneshot::{config::AdcChannelConfig, AdcChannelDriver, AdcDriver},
+        ADC1,
+    },
     delay::FreeRtos,
-    gpio::{Gpio4, PinDriver},
-    i2c::{I2cConfig, I2cDriver},
+    gpio::{AnyIOPin, AnyInputPin, Gpio1, Gpio2, Gpio4, Output, PinDriver},
+    i2c::{I2c, I2cConfig, I2cDriver},
+    peripheral::Peripheral,
     peripherals::Peripherals,
     prelude::*,
 };
+use esp_idf_svc::http::client::{Configuration as ConfiguracaoClienteHttp, EspHttpConnection};
+use esp_idf_svc::http::server::{Configuration as ConfiguracaoHttp, EspHttpServer};
+use esp_idf_svc::http::Method;
+use esp_idf_svc::io::{Read as _, Write as _};
+use esp_idf_svc::mqtt::client::{EspMqttClient, EventPayload, MqttClientConfiguration, QoS};
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use esp_idf_svc::sntp::EspSntp;
 use esp_idf_svc::sys as esp_idf_sys;
+use esp_idf_svc::wifi::{AuthMethod, BlockingWifi, ClientConfiguration, Configuration, EspWifi};
+use log::{debug, error, info, warn};
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
 use std::fs::OpenOptions;
 use std::io::Write;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 // Configurações
-const INTERVALO_LEITURA_MINUTOS: u64 = 10;
+// Cada sensor tem seu intervalo: o BMP280 é rápido e estável, o DHT11 lento e instável.
+// A leitura publicada sai com o BMP280, levando a última umidade conhecida.
+const INTERVALO_BMP280_MINUTOS: u64 = 1;
+const INTERVALO_DHT11_MINUTOS: u64 = 10;
+// Faixa aceita ao mudar os intervalos em tempo de execução: 1 min a 1 dia
+const INTERVALO_MIN_MINUTOS: u64 = 1;
+const INTERVALO_MAX_MINUTOS: u64 = 1440;
+// Deep sleep: cada boot faz uma leitura, publica e dorme pelo intervalo (para bateria).
+// `false` mantém as tasks sempre ligadas.
+const DEEP_SLEEP: bool = false;
+// Espera pelo broker antes de publicar e pelo envio antes de dormir
+const DEEP_SLEEP_ESPERA_MQTT_MS: u32 = 5000;
+const DEEP_SLEEP_ENVIO_MS: u32 = 1000;
 const ARQUIVO_BMP280: &str = "/spiffs/bmp280_data.txt";
 const ARQUIVO_DHT11: &str = "/spiffs/dht11_data.txt";
-const BMP280_ADDR: u8 = 0x76;
+// Acima disso o arquivo é reescrito mantendo só a metade mais recente das linhas
+const ARQUIVO_TAMANHO_MAX_BYTES: u64 = 64 * 1024;
+// Endereços possíveis do BMP280 (SDO em GND ou VCC), tentados nesta ordem
+const BMP280_ENDERECOS: [u8; 2] = [0x76, 0x77];
+const BMP280_CHIP_ID: u8 = 0x58;
+// Modo forçado: o BMP280 dorme entre as leituras e só mede quando solicitado.
+// `false` volta ao modo normal (medição contínua).
+const BMP280_MODO_FORCADO: bool = true;
+// Oversampling e filtro IIR do BMP280 (valores menores economizam bateria, com mais ruído)
+const BMP280_OSRS_T: Oversampling = Oversampling::X16;
+const BMP280_OSRS_P: Oversampling = Oversampling::X16;
+const BMP280_FILTRO: FiltroIIR = FiltroIIR::X16;
+// Média móvel das últimas N pressões gravadas/publicadas (1 desliga a suavização)
+const MEDIA_PRESSAO_AMOSTRAS: usize = 3;
+// Correção somada à temperatura de cada sensor (°C), ajustada depois de comparar com um
+// termômetro de referência (comando serial `temp_offset <bmp280|dht11> <graus>`)
+const OFFSET_TEMPERATURA_BMP280_C: f32 = 0.0;
+const OFFSET_TEMPERATURA_DHT11_C: f32 = 0.0;
+// Diferença entre as temperaturas do BMP280 e do DHT (já com offsets) acima da qual
+// algo está errado: autoaquecimento, fiação ruim, sensor com defeito
+const DIVERGENCIA_TEMPERATURA_MAX_C: f32 = 2.0;
+// Só compara se a leitura do DHT for recente: com intervalos diferentes ela pode ter minutos
+const DIVERGENCIA_IDADE_MAX_SEGUNDOS: u64 = 120;
+// A leitura do DHT só vai na mensagem MQTT com até esse número de `intervalo_dht` de idade;
+// depois disso (sensor falhando) a mensagem segue só com os campos do BMP280
+const DHT_PUBLICACAO_INTERVALOS_MAX: u64 = 2;
+// Leituras do DHT11 por intervalo: publica a mediana das que passarem no checksum.
+// O datasheet pede ~1 s entre leituras consecutivas.
+const DHT11_TENTATIVAS: usize = 5;
+const DHT11_INTERVALO_TENTATIVAS_MS: u32 = 1000;
+// Sensor ligado no GPIO4: DHT11 ou DHT22 (AM2302), que usam o mesmo protocolo de um fio.
+// O DHT22 aceita pulso de início mais curto, mas pede ~2 s entre leituras.
+const DHT_MODELO: ModeloDHT = ModeloDHT::Dht11;
+const DHT22_INTERVALO_TENTATIVAS_MS: u32 = 2000;
+const DHT11_INICIO_US: u32 = 18000;
+const DHT22_INICIO_US: u32 = 1100;
+// Sensor de temperatura/umidade: DHT no GPIO4 ou SHT31 no mesmo barramento I2C do BMP280
+const FONTE_UMIDADE: FonteUmidade = FonteUmidade::Dht;
+// ADDR em GND (0x44) ou VCC (0x45)
+const SHT31_ENDERECO: u8 = 0x44;
+// Medição single-shot, alta repetibilidade, com clock stretching (datasheet, tabela 9)
+const SHT31_CMD_MEDICAO: [u8; 2] = [0x2C, 0x06];
+const SHT31_CMD_SOFT_RESET: [u8; 2] = [0x30, 0xA2];
+// Tempo máximo de uma medição de alta repetibilidade
+const SHT31_MEDICAO_MS: u32 = 15;
+
+// Rede e broker MQTT. As credenciais WiFi vêm da NVS (comando serial `wifi <ssid> <senha>`);
+// este par só é usado enquanto nada foi gravado
+const WIFI_SSID: &str = "rusty-weather";
+const WIFI_SENHA: &str = "trocar-senha";
+// Limites do 802.11 e da senha WPA2 (passphrase), checados antes de gravar na NVS
+const WIFI_SSID_MAX_BYTES: usize = 32;
+const WIFI_SENHA_MIN_BYTES: usize = 8;
+const WIFI_SENHA_MAX_BYTES: usize = 63;
+const WIFI_TENTATIVAS: u32 = 5;
+const WIFI_BACKOFF_INICIAL_MS: u32 = 1000;
+const WIFI_BACKOFF_MAX_MS: u32 = 16_000;
+const MQTT_BROKER_HOST: &str = "test.mosquitto.org";
+const MQTT_BROKER_PORTA: u16 = 1883;
+const MQTT_CLIENT_ID: &str = "rusty-weather-esp32";
+// Cada unidade publica em `sensores/rusty_weather/<id>`; o último nível identifica o dispositivo no dashboard
+const MQTT_TOPICO: &str = "sensores/rusty_weather/esp32";
+// QoS das publicações. Com `MQTT_RETER_LEITURAS` o broker guarda a última leitura e a entrega
+// na hora a quem assinar depois (um dashboard recém-iniciado), mesmo que ela já seja antiga
+const MQTT_QOS: QoS = QoS::AtLeastOnce;
+const MQTT_RETER_LEITURAS: bool = false;
+
+// Task watchdog do ESP-IDF: uma task registrada que passe disso sem se reportar (sensor
+// travado, I2C preso) reinicia o chip. Esperas longas são fatiadas para continuar alimentando.
+const WATCHDOG_TIMEOUT_MS: u32 = 60_000;
+const WATCHDOG_FATIA_MS: u64 = 10_000;
+
+// Tamanho da pilha de cada task (bytes)
+const STACK_BMP280: usize = 8192;
+const STACK_DHT11: usize = 8192;
+const STACK_OCUPACAO: usize = 4096;
+const STACK_RESUMO: usize = 4096;
+const STACK_SERIAL: usize = 4096;
+const STACK_HEARTBEAT: usize = 4096;
+const STACK_LED: usize = 2048;
+const STACK_INGESTAO: usize = 8192;
+
+// Heartbeat publicado mesmo quando os sensores falham; MQTT_TOPICO + "/heartbeat",
+// para o dashboard saber de qual dispositivo ele veio
+const TOPICO_HEARTBEAT: &str = "sensores/rusty_weather/esp32/heartbeat";
+const HEARTBEAT_INTERVALO_SEGUNDOS: u64 = 60;
+
+// Servidor HTTP de configuração (POST /config), só com WiFi e fora do deep sleep
+const HTTP_CONFIG_PORTA: u16 = 80;
+const HTTP_CONFIG_CORPO_MAX: usize = 256;
+
+// Resumo horário (min/max/média) das leituras
+const TOPICO_RESUMO_HORARIO: &str = "sensores/esp32/hourly";
+// Antes disso o relógio ainda não foi sincronizado (2024-01-01T00:00:00Z)
+const EPOCH_MINIMO_VALIDO: u64 = 1_704_067_200;
+const RESUMO_POLL_MS: u32 = 30_000;
+
+// Backfill: a cada (re)conexão ao MQTT, envia ao POST /api/ingest do dashboard as linhas do
+// SPIFFS que ainda não foram enviadas. `None` desativa. Só HTTP (sem TLS).
+const INGESTAO_URL: Option<&str> = None;
+// O mesmo ADMIN_TOKEN do dashboard, enviado em `Authorization: Bearer`; sem ele a ingestão não sobe
+const INGESTAO_TOKEN: Option<&str> = None;
+const INGESTAO_BLOCO_BYTES: usize = 1024;
+const INGESTAO_TIMEOUT_SEGUNDOS: u64 = 30;
+const INGESTAO_POLL_MS: u32 = 10_000;
+
+// Gravação no SPIFFS: tentativas com backoff exponencial e buffer em RAM
+const GRAVACAO_TENTATIVAS: u32 = 3;
+const GRAVACAO_BACKOFF_MS: u32 = 50;
+const BUFFER_PENDENTE_MAX: usize = 32;
+
+// Deadband: variações menores que estas não são gravadas
+const DEADBAND_TEMPERATURA_C: f32 = 0.2;
+const DEADBAND_PRESSAO_HPA: f32 = 0.3;
+const DEADBAND_UMIDADE_PCT: f32 = 1.0;
+// Força uma gravação a cada N intervalos mesmo sem variação
+const DEADBAND_HEARTBEAT_INTERVALOS: u32 = 6;
+
+// Pressão ao nível do mar padrão usada no cálculo de altitude, até ser calibrada pelos
+// comandos seriais `sea_level <hpa>` ou `calibrate_altitude <metros>`
+const PRESSAO_REFERENCIA_HPA: f32 = 1013.25;
+// Faixa aceita na calibração (extremos já registrados ao nível do mar)
+const PRESSAO_NIVEL_MAR_MIN_HPA: f32 = 870.0;
+const PRESSAO_NIVEL_MAR_MAX_HPA: f32 = 1085.0;
+const MODO_ALTITUDE: ModoAltitude = ModoAltitude::LimitarEmZero;
+
+// Correção de deriva do BMP280 (comando serial `calibrate_pressure <ref_hpa>`)
+const NVS_NAMESPACE: &str = "rusty_weather";
+const NVS_CHAVE_OFFSET_PRESSAO: &str = "p_offset";
+const NVS_CHAVE_NIVEL_MAR: &str = "p_nivel_mar";
+const NVS_CHAVE_OFFSET_T_BMP280: &str = "t_off_bmp";
+const NVS_CHAVE_OFFSET_T_DHT11: &str = "t_off_dht";
+const NVS_CHAVE_WIFI_SSID: &str = "wifi_ssid";
+const NVS_CHAVE_WIFI_SENHA: &str = "wifi_senha";
+// Timestamp da última linha de cada arquivo já aceita pelo dashboard (backfill)
+const NVS_CHAVE_INGESTAO_BMP280: &str = "ing_bmp";
+const NVS_CHAVE_INGESTAO_DHT11: &str = "ing_dht";
+const PERSISTIR_CALIBRACAO_NVS: bool = true;
+
+// Tensão da bateria no GPIO1 (ADC1) através de um divisor resistivo; `false` para
+// alimentação por USB. Com 100k/100k o pino vê metade da tensão da bateria.
+const BATERIA_ADC: bool = false;
+const GPIO_BATERIA: i32 = 1;
+const BATERIA_DIVISOR: f32 = 2.0;
+// Média de várias conversões para reduzir o ruído do ADC
+const BATERIA_AMOSTRAS: u32 = 8;
+
+// LED de status no GPIO2 (o LED da placa na maioria dos DevKits); `false` desativa.
+// Apagado: sem WiFi/MQTT. Piscando rápido: erros repetidos de sensor. Aceso: última
+// leitura de todos os sensores ok. Piscando devagar: aguardando a primeira leitura.
+const LED_STATUS: bool = true;
+const GPIO_LED_STATUS: i32 = 2;
+const LED_PISCA_LENTO_MS: u32 = 1000;
+const LED_PISCA_RAPIDO_MS: u32 = 150;
+// Erros consecutivos de um sensor a partir dos quais o LED pisca rápido
+const LED_ERROS_REPETIDOS: u32 = 2;
+
+// Barramento I2C (ajuste conforme o roteamento da placa)
+const I2C_SDA_GPIO: i32 = 21;
+const I2C_SCL_GPIO: i32 = 22;
+const I2C_FREQUENCIA_KHZ: u32 = 100;
+
+// Segundo barramento I2C (i2c1) para sensores adicionais: (SDA, SCL). `None` desativa.
+const I2C1_PINOS: Option<(i32, i32)> = None;
+const I2C1_FREQUENCIA_KHZ: u32 = 100;
+
+// Barramento em que o BMP280 está ligado
+const BMP280_BARRAMENTO: BarramentoI2c = BarramentoI2c::I2c0;
+
+// Pino de dados do DHT (peripherals.pins.gpio4), reservado mesmo com o SHT31 como fonte
+const GPIO_DHT: i32 = 4;
+
+// Limites suportados pelo driver I2C do ESP-IDF
+const I2C_FREQUENCIA_MIN_KHZ: u32 = 10;
+const I2C_FREQUENCIA_MAX_KHZ: u32 = 1000;
+const GPIO_MAX: i32 = 48;
+
+// Varredura do barramento na inicialização: faixa de endereços de 7 bits não reservados
+const I2C_SCAN_PRIMEIRO: u8 = 0x03;
+const I2C_SCAN_ULTIMO: u8 = 0x77;
+const I2C_SCAN_TIMEOUT_TICKS: u32 = 50;
+
+// Sensor de presença (PIR). `None` desativa o controle por ocupação.
+const OCUPACAO_GPIO: Option<i32> = None;
+const OCUPACAO_COOLDOWN_SEGUNDOS: u64 = 300;
+const OCUPACAO_POLL_MS: u32 = 500;
+
+// Nível dos logs pelo EspLogger. `Debug` mostra também cada publicação e os eventos MQTT
+// (exige CONFIG_LOG_MAXIMUM_LEVEL compatível no sdkconfig).
+const NIVEL_LOG: log::LevelFilter = log::LevelFilter::Info;
+// Avisos e erros repetidos (mesma mensagem) dentro da janela viram uma só linha
+const LOG_REPETICAO_JANELA: Duration = Duration::from_secs(60);
+
+// ============================================
+// Log com Limite de Repetição
+// ============================================
+
+/// Decide se um log limitado sai agora. `chave` é a string de formato, então leituras
+/// que falham do mesmo jeito contam como repetição mesmo com números diferentes.
+/// Devolve quantas repetições foram suprimidas desde a última emitida, ou None para suprimir.
+fn permitir_log(chave: &'static str) -> Option<u32> {
+    static REPETICOES: OnceLock<Mutex<HashMap<&'static str, (Instant, u32)>>> = OnceLock::new();
+    let mut repeticoes = REPETICOES
+        .get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .unwrap();
+
+    match repeticoes.get_mut(chave) {
+        Some((emitido, suprimidas)) if emitido.elapsed() < LOG_REPETICAO_JANELA => {
+            *suprimidas += 1;
+            None
+        }
+        _ => {
+            let suprimidas = repeticoes
+                .insert(chave, (Instant::now(), 0))
+                .map_or(0, |(_, n)| n);
+            Some(suprimidas)
+        }
+    }
+}
+
+macro_rules! log_limitado {
+    ($nivel:expr, $fmt:literal $(, $arg:expr)* $(,)?) => {
+        match permitir_log($fmt) {
+            Some(0) => log::log!($nivel, $fmt $(, $arg)*),
+            Some(suprimidas) => log::log!(
+                $nivel,
+                concat!($fmt, " (+{} repetições suprimidas)") $(, $arg)*,
+                suprimidas
+            ),
+            None => {}
+        }
+    };
+}
+
+macro_rules! warn_limitado {
+    ($($t:tt)*) => { log_limitado!(log::Level::Warn, $($t)*) };
+}
+
+macro_rules! error_limitado {
+    ($($t:tt)*) => { log_limitado!(log::Level::Error, $($t)*) };
+}
 
 // ============================================
 // Estruturas de Configuração
@@ -24,33 +296,220 @@ const BMP280_ADDR: u8 = 0x76;
 
 #[derive(Clone)]
 struct Config {
-    intervalo_minutos: u64,
+    intervalo_bmp: u64,
+    intervalo_dht: u64,
+    deep_sleep: bool,
+    bmp280_modo_forcado: bool,
+    osrs_t: Oversampling,
+    osrs_p: Oversampling,
+    filtro: FiltroIIR,
+    janela_media_pressao: usize,
+    pressao_nivel_mar_hpa: f32,
+    temp_offset_bmp280: f32,
+    temp_offset_dht11: f32,
+    modelo_dht: ModeloDHT,
+    fonte_umidade: FonteUmidade,
 }
 
 impl Config {
     fn new() -> Self {
         Self {
-            intervalo_minutos: INTERVALO_LEITURA_MINUTOS,
+            intervalo_bmp: INTERVALO_BMP280_MINUTOS,
+            intervalo_dht: INTERVALO_DHT11_MINUTOS,
+            deep_sleep: DEEP_SLEEP,
+            bmp280_modo_forcado: BMP280_MODO_FORCADO,
+            osrs_t: BMP280_OSRS_T,
+            osrs_p: BMP280_OSRS_P,
+            filtro: BMP280_FILTRO,
+            janela_media_pressao: MEDIA_PRESSAO_AMOSTRAS,
+            pressao_nivel_mar_hpa: PRESSAO_REFERENCIA_HPA,
+            temp_offset_bmp280: OFFSET_TEMPERATURA_BMP280_C,
+            temp_offset_dht11: OFFSET_TEMPERATURA_DHT11_C,
+            modelo_dht: DHT_MODELO,
+            fonte_umidade: FONTE_UMIDADE,
+        }
+    }
+
+    fn set_intervalos(&mut self, bmp_minutos: u64, dht_minutos: u64) -> anyhow::Result<()> {
+        for minutos in [bmp_minutos, dht_minutos] {
+            if !(INTERVALO_MIN_MINUTOS..=INTERVALO_MAX_MINUTOS).contains(&minutos) {
+                anyhow::bail!(
+                    "intervalo fora da faixa {}-{} minutos: {}",
+                    INTERVALO_MIN_MINUTOS,
+                    INTERVALO_MAX_MINUTOS,
+                    minutos
+                );
+            }
+        }
+        self.intervalo_bmp = bmp_minutos;
+        self.intervalo_dht = dht_minutos;
+        Ok(())
+    }
+
+    fn set_pressao_nivel_mar(&mut self, hpa: f32) -> anyhow::Result<()> {
+        if !(PRESSAO_NIVEL_MAR_MIN_HPA..=PRESSAO_NIVEL_MAR_MAX_HPA).contains(&hpa) {
+            anyhow::bail!(
+                "pressão ao nível do mar fora da faixa {}-{} hPa: {}",
+                PRESSAO_NIVEL_MAR_MIN_HPA,
+                PRESSAO_NIVEL_MAR_MAX_HPA,
+                hpa
+            );
+        }
+        self.pressao_nivel_mar_hpa = hpa;
+        Ok(())
+    }
+
+    fn intervalo_bmp_ms(&self) -> u64 {
+        self.intervalo_bmp * 60 * 1000
+    }
+
+    fn intervalo_dht_ms(&self) -> u64 {
+        self.intervalo_dht * 60 * 1000
+    }
+
+    // No deep sleep os dois sensores são lidos a cada despertar
+    fn intervalo_deep_sleep_minutos(&self) -> u64 {
+        self.intervalo_bmp.min(self.intervalo_dht)
+    }
+
+    // Ajustes do BMP280, recusando combinações que não produzem leituras válidas
+    fn ajustes_bmp280(&self) -> anyhow::Result<AjustesBMP280> {
+        // A compensação da pressão depende de t_fine, calculado a partir da temperatura
+        if self.osrs_t == Oversampling::Pular {
+            anyhow::bail!("osrs_t não pode ser Pular: a pressão depende da temperatura");
+        }
+        if self.osrs_p == Oversampling::Pular {
+            anyhow::bail!("osrs_p não pode ser Pular: a pressão é publicada a cada leitura");
+        }
+        if self.janela_media_pressao == 0 {
+            anyhow::bail!("janela_media_pressao deve ser pelo menos 1");
+        }
+
+        Ok(AjustesBMP280 {
+            modo_forcado: self.bmp280_modo_forcado,
+            osrs_t: self.osrs_t,
+            osrs_p: self.osrs_p,
+            filtro: self.filtro,
+            janela_media_pressao: self.janela_media_pressao,
+        })
+    }
+}
+
+// Oversampling do BMP280 (campos osrs_t/osrs_p do registrador 0xF4)
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Oversampling {
+    Pular = 0b000,
+    X1 = 0b001,
+    X2 = 0b010,
+    X4 = 0b011,
+    X8 = 0b100,
+    X16 = 0b101,
+}
+
+// Coeficiente do filtro IIR do BMP280 (campo filter do registrador 0xF5)
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FiltroIIR {
+    Desligado = 0b000,
+    X2 = 0b001,
+    X4 = 0b010,
+    X8 = 0b011,
+    X16 = 0b100,
+}
+
+// Modelo do sensor de umidade: muda só a decodificação dos 5 bytes e os tempos
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ModeloDHT {
+    Dht11,
+    Dht22,
+}
+
+impl ModeloDHT {
+    fn nome(self) -> &'static str {
+        match self {
+            ModeloDHT::Dht11 => "DHT11",
+            ModeloDHT::Dht22 => "DHT22",
+        }
+    }
+
+    fn inicio_us(self) -> u32 {
+        match self {
+            ModeloDHT::Dht11 => DHT11_INICIO_US,
+            ModeloDHT::Dht22 => DHT22_INICIO_US,
         }
     }
 
-    fn set_intervalo(&mut self, minutos: u64) {
-        self.intervalo_minutos = minutos;
+    fn intervalo_tentativas_ms(self) -> u32 {
+        match self {
+            ModeloDHT::Dht11 => DHT11_INTERVALO_TENTATIVAS_MS,
+            ModeloDHT::Dht22 => DHT22_INTERVALO_TENTATIVAS_MS,
+        }
     }
 
-    fn intervalo_ms(&self) -> u64 {
-        self.intervalo_minutos * 60 * 1000
+    /// Converte os bytes já conferidos pelo checksum em (umidade %, temperatura °C).
+    /// DHT11: parte inteira e decimal em bytes separados. DHT22: 16 bits big-endian
+    /// em décimos, com o bit mais alto da temperatura indicando valor negativo.
+    fn decodificar(self, dados: &[u8; 5]) -> (f32, f32) {
+        match self {
+            ModeloDHT::Dht11 => (
+                dados[0] as f32 + (dados[1] as f32) * 0.1,
+                dados[2] as f32 + (dados[3] as f32) * 0.1,
+            ),
+            ModeloDHT::Dht22 => {
+                let umidade = u16::from_be_bytes([dados[0], dados[1]]) as f32 / 10.0;
+                let magnitude = u16::from_be_bytes([dados[2] & 0x7F, dados[3]]) as f32 / 10.0;
+                let temperatura = if dados[2] & 0x80 != 0 {
+                    -magnitude
+                } else {
+                    magnitude
+                };
+                (umidade, temperatura)
+            }
+        }
     }
 }
 
-#[derive(Debug)]
+// Sensor que fornece umidade (e a segunda temperatura, `temperatura_dht11` no payload)
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FonteUmidade {
+    Dht,
+    Sht31,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct AjustesBMP280 {
+    // Sensor em sleep entre leituras, disparando uma medição por `ler_dados`
+    modo_forcado: bool,
+    osrs_t: Oversampling,
+    osrs_p: Oversampling,
+    filtro: FiltroIIR,
+    janela_media_pressao: usize,
+}
+
+// Tratamento de altitudes negativas (pressão acima da referência)
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ModoAltitude {
+    LimitarEmZero,
+    ReportarNegativa,
+}
+
+#[derive(Debug, Clone, Copy)]
 struct DadosBMP280 {
     temperatura: f32,
+    // Pressão já corrigida pelo offset de calibração
     pressao: f32,
+    // Pressão compensada pelo datasheet (já com a média móvel), antes do offset
+    pressao_bruta: f32,
     altitude: f32,
+    // A pressão medida está acima da referência: a altitude calculada seria negativa
+    abaixo_referencia: bool,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 struct DadosDHT11 {
     temperatura: f32,
     umidade: f32,
@@ -81,10 +540,28 @@ struct BMP280<'a> {
     addr: u8,
     calibracao: CalibracaoBMP280,
     t_fine: i32,
+    // Correção somada à pressão medida (hPa), obtida por `calibrate_pressure`
+    offset_pressao_hpa: f32,
+    // Referência do cálculo de altitude e correção da temperatura, sincronizadas com
+    // `Config` a cada leitura
+    pressao_nivel_mar_hpa: f32,
+    offset_temperatura_c: f32,
+    ajustes: AjustesBMP280,
+    // Últimas pressões compensadas (hPa) da média móvel
+    pressoes_recentes: VecDeque<f32>,
 }
 
+// mode[1:0] do registrador ctrl_meas (0xF4)
+const BMP280_MODO_SLEEP: u8 = 0b00;
+const BMP280_MODO_FORCED: u8 = 0b01;
+const BMP280_MODO_NORMAL: u8 = 0b11;
+
 impl<'a> BMP280<'a> {
-    fn new(i2c: Arc<Mutex<I2cDriver<'a>>>, addr: u8) -> Result<Self, esp_idf_sys::EspError> {
+    fn new(
+        i2c: Arc<Mutex<I2cDriver<'a>>>,
+        addr: u8,
+        ajustes: AjustesBMP280,
+    ) -> Result<Self, esp_idf_sys::EspError> {
         let mut sensor = Self {
             i2c,
             addr,
@@ -103,16 +580,22 @@ impl<'a> BMP280<'a> {
                 dig_p9: 0,
             },
             t_fine: 0,
+            offset_pressao_hpa: 0.0,
+            pressao_nivel_mar_hpa: PRESSAO_REFERENCIA_HPA,
+            offset_temperatura_c: 0.0,
+            ajustes,
+            pressoes_recentes: VecDeque::with_capacity(ajustes.janela_media_pressao),
         };
 
         // Verificar chip ID
         let mut chip_id = [0u8; 1];
         sensor.read_register(0xD0, &mut chip_id)?;
 
-        if chip_id[0] != 0x58 {
-            println!(
-                "Aviso: Chip ID inesperado: 0x{:02X} (esperado 0x58)",
-                chip_id[0]
+        if chip_id[0] != BMP280_CHIP_ID {
+            warn_limitado!(
+                "Chip ID inesperado: 0x{:02X} (esperado 0x{:02X})",
+                chip_id[0],
+                BMP280_CHIP_ID
             );
         }
 
@@ -129,6 +612,37 @@ impl<'a> BMP280<'a> {
         Ok(sensor)
     }
 
+    /// Procura o sensor em `BMP280_ENDERECOS` e inicializa no primeiro endereço cujo
+    /// chip ID confere. O endereço encontrado fica em `addr` para as reinicializações.
+    fn detectar(
+        i2c: Arc<Mutex<I2cDriver<'a>>>,
+        ajustes: AjustesBMP280,
+    ) -> Result<Self, esp_idf_sys::EspError> {
+        for addr in BMP280_ENDERECOS {
+            let mut chip_id = [0u8; 1];
+            let lido = i2c
+                .lock()
+                .unwrap()
+                .write_read(addr, &[0xD0], &mut chip_id, 1000);
+
+            match lido {
+                Ok(()) if chip_id[0] == BMP280_CHIP_ID => {
+                    info!("✓ BMP280 detectado em 0x{:02X}", addr);
+                    return Self::new(i2c, addr, ajustes);
+                }
+                Ok(()) => info!(
+                    "BMP280: chip ID 0x{:02X} em 0x{:02X}, tentando o próximo endereço",
+                    chip_id[0], addr
+                ),
+                Err(e) => debug!("BMP280: sem resposta em 0x{:02X}: {:?}", addr, e),
+            }
+        }
+
+        Err(esp_idf_sys::EspError::from_infallible::<
+            { esp_idf_sys::ESP_ERR_NOT_FOUND },
+        >())
+    }
+
     fn ler_calibracao(&mut self) -> Result<(), esp_idf_sys::EspError> {
         let mut calib = [0u8; 24];
         self.read_register(0x88, &mut calib)?;
@@ -147,12 +661,12 @@ impl<'a> BMP280<'a> {
         self.calibracao.dig_p8 = i16::from_le_bytes([calib[20], calib[21]]);
         self.calibracao.dig_p9 = i16::from_le_bytes([calib[22], calib[23]]);
 
-        println!("Calibração BMP280 carregada:");
-        println!(
+        debug!("Calibração BMP280 carregada:");
+        debug!(
             "  T1={}, T2={}, T3={}",
             self.calibracao.dig_t1, self.calibracao.dig_t2, self.calibracao.dig_t3
         );
-        println!(
+        debug!(
             "  P1={}, P2={}, P3={}",
             self.calibracao.dig_p1, self.calibracao.dig_p2, self.calibracao.dig_p3
         );
@@ -160,14 +674,23 @@ impl<'a> BMP280<'a> {
         Ok(())
     }
 
+    // osrs_t[7:5], osrs_p[4:2] conforme os ajustes, mode[1:0] conforme `modo`
+    fn ctrl_meas(&self, modo: u8) -> u8 {
+        ((self.ajustes.osrs_t as u8) << 5) | ((self.ajustes.osrs_p as u8) << 2) | modo
+    }
+
     fn init(&self) -> Result<(), esp_idf_sys::EspError> {
-        // Configurar modo normal, oversampling x16 para temp e pressão
-        // osrs_t[7:5] = 101 (x16), osrs_p[4:2] = 101 (x16), mode[1:0] = 11 (normal)
-        self.write_register(0xF4, 0b10110111)?;
+        // No modo forçado o sensor fica em sleep até cada leitura
+        let modo = if self.ajustes.modo_forcado {
+            BMP280_MODO_SLEEP
+        } else {
+            BMP280_MODO_NORMAL
+        };
+        self.write_register(0xF4, self.ctrl_meas(modo))?;
 
-        // Configurar standby time = 0.5ms, filter = 16
-        // t_sb[7:5] = 000, filter[4:2] = 100, spi3w_en[0] = 0
-        self.write_register(0xF5, 0b00010000)?;
+        // Configurar standby time = 0.5ms e o filtro IIR
+        // t_sb[7:5] = 000, filter[4:2] conforme os ajustes, spi3w_en[0] = 0
+        self.write_register(0xF5, (self.ajustes.filtro as u8) << 2)?;
 
         FreeRtos::delay_ms(100);
         Ok(())
@@ -223,11 +746,44 @@ impl<'a> BMP280<'a> {
         (p as f32) / 256.0
     }
 
-    fn calcular_altitude(&self, pressao_hpa: f32) -> f32 {
-        44330.0 * (1.0 - (pressao_hpa / 1013.25_f32).powf(0.1903))
+    /// Fórmula barométrica. Pressões acima de `pressao_nivel_mar_hpa` resultam em
+    /// altitude negativa, que é limitada a zero ou mantida conforme `MODO_ALTITUDE`.
+    fn calcular_altitude(&self, pressao_hpa: f32) -> (f32, bool) {
+        let altitude = 44330.0 * (1.0 - (pressao_hpa / self.pressao_nivel_mar_hpa).powf(0.1903));
+        let negativa = altitude < 0.0;
+
+        match MODO_ALTITUDE {
+            ModoAltitude::LimitarEmZero if negativa => (0.0, true),
+            _ => (altitude, negativa),
+        }
+    }
+
+    /// Acrescenta `pressao_hpa` à janela e devolve a média das últimas
+    /// `janela_media_pressao` leituras (menos enquanto a janela enche).
+    fn media_pressao(&mut self, pressao_hpa: f32) -> f32 {
+        if self.pressoes_recentes.len() >= self.ajustes.janela_media_pressao {
+            self.pressoes_recentes.pop_front();
+        }
+        self.pressoes_recentes.push_back(pressao_hpa);
+
+        self.pressoes_recentes.iter().sum::<f32>() / self.pressoes_recentes.len() as f32
     }
 
     fn ler_dados(&mut self) -> Result<DadosBMP280, esp_idf_sys::EspError> {
+        if self.ajustes.modo_forcado {
+            self.ler_dados_forced()
+        } else {
+            self.ler_medicao()
+        }
+    }
+
+    // Dispara uma única medição; o sensor volta sozinho ao sleep ao terminar
+    fn ler_dados_forced(&mut self) -> Result<DadosBMP280, esp_idf_sys::EspError> {
+        self.write_register(0xF4, self.ctrl_meas(BMP280_MODO_FORCED))?;
+        self.ler_medicao()
+    }
+
+    fn ler_medicao(&mut self) -> Result<DadosBMP280, esp_idf_sys::EspError> {
         // Aguardar medição estar pronta
         let mut status = [0u8; 1];
         for _ in 0..10 {
@@ -252,31 +808,42 @@ impl<'a> BMP280<'a> {
 
         // Compensar pressão (usa t_fine)
         let pressao_pa = self.compensar_pressao(adc_p);
-        let pressao_hpa = pressao_pa / 100.0;
+        let pressao_bruta = self.media_pressao(pressao_pa / 100.0);
+        let pressao_hpa = pressao_bruta + self.offset_pressao_hpa;
 
         // Calcular altitude
-        let altitude = self.calcular_altitude(pressao_hpa);
+        let (altitude, abaixo_referencia) = self.calcular_altitude(pressao_hpa);
 
+        // O offset só vale para a saída: a compensação usa a temperatura do sensor
         Ok(DadosBMP280 {
-            temperatura,
+            temperatura: temperatura + self.offset_temperatura_c,
             pressao: pressao_hpa,
+            pressao_bruta,
             altitude,
+            abaixo_referencia,
         })
     }
 }
 
 // ============================================
-// Driver DHT11 Completo
+// Driver DHT11 Completo (também lê o DHT22)
 // ============================================
 
 struct DHT11<'a> {
     pin: PinDriver<'a, Gpio4, esp_idf_svc::hal::gpio::InputOutput>,
+    modelo: ModeloDHT,
+    // Correção somada à temperatura (°C), sincronizada com `Config` a cada leitura
+    offset_temperatura_c: f32,
 }
 
 impl<'a> DHT11<'a> {
-    fn new(pin: Gpio4) -> Result<Self, esp_idf_sys::EspError> {
+    fn new(pin: Gpio4, modelo: ModeloDHT) -> Result<Self, esp_idf_sys::EspError> {
         let pin = PinDriver::input_output_od(pin)?;
-        Ok(Self { pin })
+        Ok(Self {
+            pin,
+            modelo,
+            offset_temperatura_c: 0.0,
+        })
     }
 
     fn esperar_nivel(
@@ -334,18 +901,18 @@ impl<'a> DHT11<'a> {
         FreeRtos::delay_ms(1);
 
         self.pin.set_low()?;
-        esp_idf_svc::hal::delay::Ets::delay_us(18000); // 18ms
+        esp_idf_svc::hal::delay::Ets::delay_us(self.modelo.inicio_us()); // 18ms no DHT11, ~1ms no DHT22
 
         self.pin.set_high()?;
         esp_idf_svc::hal::delay::Ets::delay_us(40);
 
-        // 2. Aguardar resposta do DHT11
+        // 2. Aguardar resposta do sensor
         // DHT puxa baixo por 80us
         if let Err(_) = self.esperar_nivel(false, 100) {
             unsafe {
                 esp_idf_sys::portENABLE_INTERRUPTS();
             }
-            println!("DHT11: Timeout esperando resposta (baixo)");
+            warn_limitado!("{}: Timeout esperando resposta (baixo)", self.modelo.nome());
             return Err(esp_idf_sys::EspError::from_infallible::<
                 { esp_idf_sys::ESP_ERR_TIMEOUT },
             >());
@@ -356,7 +923,7 @@ impl<'a> DHT11<'a> {
             unsafe {
                 esp_idf_sys::portENABLE_INTERRUPTS();
             }
-            println!("DHT11: Timeout esperando resposta (alto)");
+            warn_limitado!("{}: Timeout esperando resposta (alto)", self.modelo.nome());
             return Err(esp_idf_sys::EspError::from_infallible::<
                 { esp_idf_sys::ESP_ERR_TIMEOUT },
             >());
@@ -387,9 +954,11 @@ impl<'a> DHT11<'a> {
             .wrapping_add(dados[3]);
 
         if checksum != dados[4] {
-            println!(
-                "DHT11: Checksum inválido! Calculado: {}, Recebido: {}",
-                checksum, dados[4]
+            warn_limitado!(
+                "{}: Checksum inválido! Calculado: {}, Recebido: {}",
+                self.modelo.nome(),
+                checksum,
+                dados[4]
             );
             return Err(esp_idf_sys::EspError::from_infallible::<
                 { esp_idf_sys::ESP_ERR_INVALID_CRC },
@@ -397,220 +966,2313 @@ impl<'a> DHT11<'a> {
         }
 
         // 5. Converter dados
-        let umidade = dados[0] as f32 + (dados[1] as f32) * 0.1;
-        let temperatura = dados[2] as f32 + (dados[3] as f32) * 0.1;
+        let (umidade, temperatura) = self.modelo.decodificar(&dados);
+        let temperatura = temperatura + self.offset_temperatura_c;
 
         Ok(DadosDHT11 {
             temperatura,
             umidade,
         })
     }
-}
-// ============================================
-// Funções de Gravação
-// ============================================
 
-fn gravar_bmp280(dados: &DadosBMP280) -> std::io::Result<()> {
-    let mut file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(ARQUIVO_BMP280)?;
+    /// Faz até `DHT11_TENTATIVAS` leituras e devolve a mediana de temperatura e
+    /// umidade das que deram certo. Só falha se todas falharem (com o último erro).
+    /// Cada tentativa entra em `contadores`, não só o resultado final.
+    fn ler_dados_estavel(
+        &mut self,
+        contadores: &ContadoresLeitura,
+    ) -> Result<DadosDHT11, esp_idf_sys::EspError> {
+        let mut temperaturas = Vec::with_capacity(DHT11_TENTATIVAS);
+        let mut umidades = Vec::with_capacity(DHT11_TENTATIVAS);
+        let mut ultimo_erro = None;
+
+        for tentativa in 0..DHT11_TENTATIVAS {
+            if tentativa > 0 {
+                FreeRtos::delay_ms(self.modelo.intervalo_tentativas_ms());
+            }
 
-    let timestamp = esp_idf_sys::esp_timer_get_time() / 1000000;
-    let linha = format!(
-        "{},{:.2},{:.2},{:.2}\n",
-        timestamp, dados.temperatura, dados.pressao, dados.altitude
-    );
+            let resultado = self.ler_dados();
+            contadores.registrar(&resultado);
+            match resultado {
+                Ok(dados) => {
+                    temperaturas.push(dados.temperatura);
+                    umidades.push(dados.umidade);
+                }
+                Err(e) => ultimo_erro = Some(e),
+            }
+        }
 
-    file.write_all(linha.as_bytes())?;
-    file.flush()?;
+        if temperaturas.is_empty() {
+            return Err(ultimo_erro.unwrap_or_else(|| {
+                esp_idf_sys::EspError::from_infallible::<{ esp_idf_sys::ESP_ERR_TIMEOUT }>()
+            }));
+        }
 
-    println!(
-        "✓ BMP280: T={:.2}°C, P={:.2}hPa, Alt={:.2}m",
-        dados.temperatura, dados.pressao, dados.altitude
-    );
+        if temperaturas.len() < DHT11_TENTATIVAS {
+            warn_limitado!(
+                "{}: {}/{} leituras válidas",
+                self.modelo.nome(),
+                temperaturas.len(),
+                DHT11_TENTATIVAS
+            );
+        }
 
-    Ok(())
+        Ok(DadosDHT11 {
+            temperatura: mediana(&mut temperaturas),
+            umidade: mediana(&mut umidades),
+        })
+    }
 }
 
-fn gravar_dht11(dados: &DadosDHT11) -> std::io::Result<()> {
-    let mut file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(ARQUIVO_DHT11)?;
+// Mediana (com número par de valores, a média dos dois centrais); `valores` não pode ser vazio
+fn mediana(valores: &mut [f32]) -> f32 {
+    valores.sort_by(f32::total_cmp);
+    let meio = valores.len() / 2;
 
-    let timestamp = esp_idf_sys::esp_timer_get_time() / 1000000;
-    let linha = format!(
-        "{},{:.2},{:.2}\n",
-        timestamp, dados.temperatura, dados.umidade
-    );
+    if valores.len() % 2 == 0 {
+        (valores[meio - 1] + valores[meio]) / 2.0
+    } else {
+        valores[meio]
+    }
+}
 
-    file.write_all(linha.as_bytes())?;
-    file.flush()?;
+// ============================================
+// Bateria (ADC)
+// ============================================
 
-    println!(
-        "✓ DHT11: T={:.2}°C, RH={:.2}%",
-        dados.temperatura, dados.umidade
-    );
+struct MonitorBateria {
+    canal: AdcChannelDriver<'static, Gpio1, AdcDriver<'static, ADC1>>,
+}
 
-    Ok(())
+impl MonitorBateria {
+    fn new(adc: ADC1, pino: Gpio1) -> Result<Self, esp_idf_sys::EspError> {
+        // 11 dB cobre até ~3,1 V no pino; a calibração devolve a leitura já em mV
+        let config = AdcChannelConfig {
+            attenuation: DB_11,
+            calibration: true,
+            ..Default::default()
+        };
+        let canal = AdcChannelDriver::new(AdcDriver::new(adc)?, pino, &config)?;
+
+        info!("✓ Monitor de bateria inicializado (GPIO1)");
+        Ok(Self { canal })
+    }
+
+    fn ler_volts(&mut self) -> Result<f32, esp_idf_sys::EspError> {
+        let mut soma_mv: u32 = 0;
+        for _ in 0..BATERIA_AMOSTRAS {
+            soma_mv += self.canal.read()? as u32;
+        }
+
+        let pino_v = soma_mv as f32 / BATERIA_AMOSTRAS as f32 / 1000.0;
+        Ok(pino_v * BATERIA_DIVISOR)
+    }
 }
 
 // ============================================
-// Tasks Assíncronas
+// Driver SHT31
 // ============================================
 
-fn task_bmp280(config: Arc<Mutex<Config>>, i2c: Arc<Mutex<I2cDriver<'static>>>) {
-    println!("🚀 Task BMP280 iniciada");
+struct SHT31<'a> {
+    i2c: Arc<Mutex<I2cDriver<'a>>>,
+    addr: u8,
+    // Correção somada à temperatura (°C), sincronizada com `Config` a cada leitura
+    offset_temperatura_c: f32,
+}
 
-    let mut sensor = match BMP280::new(i2c, BMP280_ADDR) {
-        Ok(s) => s,
-        Err(e) => {
-            println!("❌ Erro ao inicializar BMP280: {:?}", e);
-            return;
-        }
-    };
+impl<'a> SHT31<'a> {
+    fn new(i2c: Arc<Mutex<I2cDriver<'a>>>, addr: u8) -> Result<Self, esp_idf_sys::EspError> {
+        let sensor = Self {
+            i2c,
+            addr,
+            offset_temperatura_c: 0.0,
+        };
 
-    let mut contador_erros = 0;
-    const MAX_ERROS: u32 = 5;
+        // Soft reset também confirma que há algo respondendo no endereço
+        sensor
+            .i2c
+            .lock()
+            .unwrap()
+            .write(addr, &SHT31_CMD_SOFT_RESET, 1000)?;
+        FreeRtos::delay_ms(2);
 
-    loop {
-        match sensor.ler_dados() {
-            Ok(dados) => {
-                if let Err(e) = gravar_bmp280(&dados) {
-                    println!("⚠️  Erro ao gravar BMP280: {:?}", e);
-                }
-                contador_erros = 0;
-            }
-            Err(e) => {
-                contador_erros += 1;
-                println!(
-                    "⚠️  Erro ao ler BMP280 ({}/{}): {:?}",
-                    contador_erros, MAX_ERROS, e
-                );
+        info!("✓ SHT31 inicializado em 0x{:02X}", addr);
+        Ok(sensor)
+    }
 
-                if contador_erros >= MAX_ERROS {
-                    println!("❌ BMP280: Muitos erros consecutivos, reiniciando sensor...");
-                    FreeRtos::delay_ms(1000);
-                    // Tentar reinicializar
-                    match BMP280::new(Arc::clone(&sensor.i2c), BMP280_ADDR) {
-                        Ok(s) => {
-                            sensor = s;
-                            contador_erros = 0;
-                            println!("✓ BMP280 reinicializado");
-                        }
-                        Err(e) => {
-                            println!("❌ Falha ao reinicializar BMP280: {:?}", e);
-                        }
-                    }
-                }
+    fn ler_dados(&mut self) -> Result<DadosDHT11, esp_idf_sys::EspError> {
+        let mut dados = [0u8; 6];
+        {
+            let mut i2c = self.i2c.lock().unwrap();
+            i2c.write(self.addr, &SHT31_CMD_MEDICAO, 1000)?;
+            FreeRtos::delay_ms(SHT31_MEDICAO_MS);
+            i2c.read(self.addr, &mut dados, 1000)?;
+        }
+
+        // Temperatura e umidade: 2 bytes big-endian + CRC-8 cada
+        for palavra in dados.chunks(3) {
+            let calculado = crc8_sht31(&palavra[..2]);
+            if calculado != palavra[2] {
+                warn_limitado!(
+                    "SHT31: CRC inválido! Calculado: {}, Recebido: {}",
+                    calculado,
+                    palavra[2]
+                );
+                return Err(esp_idf_sys::EspError::from_infallible::<
+                    { esp_idf_sys::ESP_ERR_INVALID_CRC },
+                >());
             }
         }
 
-        let intervalo = config.lock().unwrap().intervalo_ms();
-        thread::sleep(Duration::from_millis(intervalo));
+        let bruto_t = u16::from_be_bytes([dados[0], dados[1]]) as f32;
+        let bruto_rh = u16::from_be_bytes([dados[3], dados[4]]) as f32;
+
+        Ok(DadosDHT11 {
+            temperatura: -45.0 + 175.0 * bruto_t / 65535.0 + self.offset_temperatura_c,
+            umidade: 100.0 * bruto_rh / 65535.0,
+        })
     }
 }
 
-fn task_dht11(config: Arc<Mutex<Config>>, gpio4: Gpio4) {
-    println!("🚀 Task DHT11 iniciada");
-
-    let mut sensor = match DHT11::new(gpio4) {
-        Ok(s) => s,
-        Err(e) => {
-            println!("❌ Erro ao inicializar DHT11: {:?}", e);
-            return;
+// CRC-8 do SHT31: polinômio 0x31, valor inicial 0xFF
+fn crc8_sht31(bytes: &[u8]) -> u8 {
+    let mut crc: u8 = 0xFF;
+    for byte in bytes {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x31
+            } else {
+                crc << 1
+            };
         }
-    };
+    }
+    crc
+}
 
-    let mut contador_erros = 0;
-    const MAX_ERROS: u32 = 5;
+// Sensor de umidade em uso, escolhido por `Config::fonte_umidade`
+enum SensorUmidade<'a> {
+    Dht(DHT11<'a>),
+    Sht31(SHT31<'a>),
+}
 
-    loop {
-        match sensor.ler_dados() {
-            Ok(dados) => {
-                if let Err(e) = gravar_dht11(&dados) {
-                    println!("⚠️  Erro ao gravar DHT11: {:?}", e);
-                }
-                contador_erros = 0;
-            }
-            Err(e) => {
-                contador_erros += 1;
-                println!(
-                    "⚠️  Erro ao ler DHT11 ({}/{}): {:?}",
-                    contador_erros, MAX_ERROS, e
-                );
+impl<'a> SensorUmidade<'a> {
+    fn abrir(
+        config: &Config,
+        gpio4: Gpio4,
+        i2c: Arc<Mutex<I2cDriver<'a>>>,
+    ) -> Result<Self, esp_idf_sys::EspError> {
+        Ok(match config.fonte_umidade {
+            FonteUmidade::Dht => SensorUmidade::Dht(DHT11::new(gpio4, config.modelo_dht)?),
+            FonteUmidade::Sht31 => SensorUmidade::Sht31(SHT31::new(i2c, SHT31_ENDERECO)?),
+        })
+    }
 
-                if contador_erros >= MAX_ERROS {
-                    println!("❌ DHT11: Muitos erros consecutivos");
-                    contador_erros = 0;
-                }
-            }
+    fn nome(&self) -> &'static str {
+        match self {
+            SensorUmidade::Dht(sensor) => sensor.modelo.nome(),
+            SensorUmidade::Sht31(_) => "SHT31",
+        }
+    }
+
+    fn set_offset_temperatura(&mut self, offset_c: f32) {
+        match self {
+            SensorUmidade::Dht(sensor) => sensor.offset_temperatura_c = offset_c,
+            SensorUmidade::Sht31(sensor) => sensor.offset_temperatura_c = offset_c,
         }
+    }
 
-        let intervalo = config.lock().unwrap().intervalo_ms();
-        thread::sleep(Duration::from_millis(intervalo));
+    /// O DHT precisa da mediana de várias leituras; o SHT31 é estável numa só
+    fn ler_dados(
+        &mut self,
+        contadores: &ContadoresLeitura,
+    ) -> Result<DadosDHT11, esp_idf_sys::EspError> {
+        match self {
+            SensorUmidade::Dht(sensor) => sensor.ler_dados_estavel(contadores),
+            SensorUmidade::Sht31(sensor) => {
+                let resultado = sensor.ler_dados();
+                contadores.registrar(&resultado);
+                resultado
+            }
+        }
     }
 }
-
 // ============================================
-// Main
+// Configuração do I2C
 // ============================================
 
-fn main() -> anyhow::Result<()> {
-    esp_idf_svc::sys::link_patches();
-    esp_idf_svc::log::EspLogger::initialize_default();
+type BarramentoCompartilhado = Arc<Mutex<I2cDriver<'static>>>;
 
-    println!("\n╔════════════════════════════════════════╗");
-    println!("║  Sistema de Leitura de Sensores       ║");
-    println!("║  ESP32-S3 + BMP280 + DHT11            ║");
-    println!("╚════════════════════════════════════════╝\n");
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BarramentoI2c {
+    I2c0,
+    I2c1,
+}
 
-    let config = Arc::new(Mutex::new(Config::new()));
-    let peripherals = Peripherals::take()?;
+fn criar_barramento_i2c<I2C: I2c>(
+    nome: &str,
+    periferico: impl Peripheral<P = I2C> + 'static,
+    sda: i32,
+    scl: i32,
+    frequencia_khz: u32,
+) -> anyhow::Result<BarramentoCompartilhado> {
+    validar_pinos_i2c(sda, scl)?;
+    let frequencia_khz = validar_frequencia_i2c(frequencia_khz);
 
-    // Configurar I2C para BMP280
-    println!("⚙️  Configurando I2C...");
     let i2c_config = I2cConfig::new()
-        .baudrate(100.kHz().into())
+        .baudrate(frequencia_khz.kHz().into())
         .sda_enable_pullup(true)
         .scl_enable_pullup(true);
 
-    let i2c = I2cDriver::new(
-        peripherals.i2c0,
-        peripherals.pins.gpio21,
-        peripherals.pins.gpio22,
-        &i2c_config,
-    )?;
+    // SAFETY: `validar_pinos_i2c` garante pinos distintos e fora de `gpios_ocupados`, e o
+    // conflito entre i2c0 e i2c1 é verificado em `main`
+    let sda_pin = unsafe { AnyIOPin::new(sda) };
+    let scl_pin = unsafe { AnyIOPin::new(scl) };
 
-    let i2c = Arc::new(Mutex::new(i2c));
+    let i2c = I2cDriver::new(periferico, sda_pin, scl_pin, &i2c_config)?;
+    info!(
+        "   {}: SDA=GPIO{}, SCL=GPIO{}, {} kHz",
+        nome, sda, scl, frequencia_khz
+    );
 
-    println!("⚙️  Configurando GPIO para DHT11...");
-    let gpio4 = peripherals.pins.gpio4;
+    Ok(Arc::new(Mutex::new(i2c)))
+}
 
-    // Criar threads
-    let config_bmp = Arc::clone(&config);
-    let i2c_bmp = Arc::clone(&i2c);
+/// Sonda cada endereço do barramento com uma escrita vazia e lista os que respondem
+/// com ACK. Diagnóstico para fiação errada ou sensor em endereço inesperado.
+fn escanear_i2c(nome: &str, barramento: &BarramentoCompartilhado) -> Vec<u8> {
+    let mut i2c = barramento.lock().unwrap();
 
-    let handle_bmp = thread::Builder::new()
-        .stack_size(8192)
-        .name("bmp280".to_string())
-        .spawn(move || task_bmp280(config_bmp, i2c_bmp))?;
+    let encontrados: Vec<u8> = (I2C_SCAN_PRIMEIRO..=I2C_SCAN_ULTIMO)
+        .filter(|&addr| i2c.write(addr, &[], I2C_SCAN_TIMEOUT_TICKS).is_ok())
+        .collect();
 
-    let config_dht = Arc::clone(&config);
-    let handle_dht = thread::Builder::new()
-        .stack_size(8192)
-        .name("dht11".to_string())
-        .spawn(move || task_dht11(config_dht, gpio4))?;
+    if encontrados.is_empty() {
+        warn!(
+            "⚠️  {}: nenhum dispositivo respondeu (verifique a fiação)",
+            nome
+        );
+    } else {
+        let lista: Vec<String> = encontrados.iter().map(|a| format!("0x{:02X}", a)).collect();
+        info!("   {}: dispositivos em {}", nome, lista.join(", "));
+    }
+
+    encontrados
+}
+
+fn validar_frequencia_i2c(khz: u32) -> u32 {
+    let efetiva = khz.clamp(I2C_FREQUENCIA_MIN_KHZ, I2C_FREQUENCIA_MAX_KHZ);
+
+    if efetiva != khz {
+        warn!(
+            "⚠️  Frequência I2C de {} kHz fora do intervalo suportado ({}-{} kHz), usando {} kHz",
+            khz, I2C_FREQUENCIA_MIN_KHZ, I2C_FREQUENCIA_MAX_KHZ, efetiva
+        );
+    }
+
+    efetiva
+}
+
+fn validar_pinos_i2c(sda: i32, scl: i32) -> anyhow::Result<()> {
+    if !(0..=GPIO_MAX).contains(&sda) || !(0..=GPIO_MAX).contains(&scl) {
+        anyhow::bail!("Pinos I2C inválidos: SDA={}, SCL={}", sda, scl);
+    }
+
+    if sda == scl {
+        anyhow::bail!("SDA e SCL não podem usar o mesmo GPIO ({})", sda);
+    }
+
+    if let Some((gpio, dono)) = gpios_ocupados()
+        .into_iter()
+        .find(|(gpio, _)| [sda, scl].contains(gpio))
+    {
+        anyhow::bail!("GPIO{} do I2C já é usado pelo {}", gpio, dono);
+    }
+
+    Ok(())
+}
+
+// GPIOs que outros drivers tomam conforme a configuração
+fn gpios_ocupados() -> Vec<(i32, &'static str)> {
+    let mut ocupados = vec![(GPIO_DHT, "DHT")];
+    if LED_STATUS {
+        ocupados.push((GPIO_LED_STATUS, "LED de status"));
+    }
+    if BATERIA_ADC {
+        ocupados.push((GPIO_BATERIA, "ADC da bateria"));
+    }
+    if let Some(gpio) = OCUPACAO_GPIO {
+        ocupados.push((gpio, "sensor de ocupação"));
+    }
+    ocupados
+}
+
+// ============================================
+// Funções de Gravação
+// ============================================
 
-    println!("\n✓ Sistema iniciado!");
-    println!(
-        "📊 Intervalo de leitura: {} minutos",
-        config.lock().unwrap().intervalo_minutos
+// Linhas que falharam ao gravar ficam em RAM até a próxima tentativa
+struct BufferPendente {
+    arquivo: &'static str,
+    linhas: VecDeque<String>,
+}
+
+impl BufferPendente {
+    fn new(arquivo: &'static str) -> Self {
+        Self {
+            arquivo,
+            linhas: VecDeque::new(),
+        }
+    }
+
+    fn gravar(&mut self, linha: String) -> std::io::Result<()> {
+        self.linhas.push_back(linha);
+
+        if self.linhas.len() > BUFFER_PENDENTE_MAX {
+            self.linhas.pop_front();
+            warn_limitado!(
+                "⚠️  Buffer pendente de {} cheio, descartando leitura mais antiga",
+                self.arquivo
+            );
+        }
+
+        // Sem rotação a gravação continua; a falha de espaço cai no retry/buffer
+        if let Err(e) = rotacionar_se_cheio(self.arquivo) {
+            warn_limitado!("⚠️  Erro ao rotacionar {}: {:?}", self.arquivo, e);
+        }
+
+        let conteudo: String = self.linhas.iter().map(String::as_str).collect();
+        escrever_com_retry(self.arquivo, &conteudo)?;
+
+        if self.linhas.len() > 1 {
+            info!(
+                "✓ {} leituras pendentes gravadas em {}",
+                self.linhas.len() - 1,
+                self.arquivo
+            );
+        }
+        self.linhas.clear();
+
+        Ok(())
+    }
+}
+
+// Suprime leituras que variam menos que o deadband em todos os campos
+struct FiltroDeadband<const N: usize> {
+    limites: [f32; N],
+    ultimo: Option<[f32; N]>,
+    intervalos_sem_gravar: u32,
+}
+
+impl<const N: usize> FiltroDeadband<N> {
+    fn new(limites: [f32; N]) -> Self {
+        Self {
+            limites,
+            ultimo: None,
+            intervalos_sem_gravar: 0,
+        }
+    }
+
+    fn deve_gravar(&mut self, valores: [f32; N]) -> bool {
+        let mudou = match self.ultimo {
+            None => true,
+            Some(ultimo) => ultimo
+                .iter()
+                .zip(valores.iter())
+                .zip(self.limites.iter())
+                .any(|((u, v), limite)| (v - u).abs() >= *limite),
+        };
+
+        let heartbeat = self.intervalos_sem_gravar + 1 >= DEADBAND_HEARTBEAT_INTERVALOS;
+
+        if mudou || heartbeat {
+            self.ultimo = Some(valores);
+            self.intervalos_sem_gravar = 0;
+            true
+        } else {
+            self.intervalos_sem_gravar += 1;
+            false
+        }
+    }
+}
+
+// Evita encher a partição SPIFFS: descarta a metade mais antiga das linhas
+fn rotacionar_se_cheio(caminho: &str) -> std::io::Result<()> {
+    let tamanho = match std::fs::metadata(caminho) {
+        Ok(m) => m.len(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    if tamanho <= ARQUIVO_TAMANHO_MAX_BYTES {
+        return Ok(());
+    }
+
+    let conteudo = std::fs::read_to_string(caminho)?;
+    let linhas: Vec<&str> = conteudo.lines().collect();
+    let manter = &linhas[linhas.len() / 2..];
+
+    let mut novo = manter.join("\n");
+    novo.push('\n');
+    std::fs::write(caminho, novo)?;
+
+    info!(
+        "♻️  {} rotacionado: {} bytes, {} de {} linhas mantidas",
+        caminho,
+        tamanho,
+        manter.len(),
+        linhas.len()
+    );
+    Ok(())
+}
+
+fn anexar_arquivo(caminho: &str, conteudo: &str) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(caminho)?;
+
+    file.write_all(conteudo.as_bytes())?;
+    file.flush()
+}
+
+fn escrever_com_retry(caminho: &str, conteudo: &str) -> std::io::Result<()> {
+    let mut backoff_ms = GRAVACAO_BACKOFF_MS;
+
+    for tentativa in 1..=GRAVACAO_TENTATIVAS {
+        match anexar_arquivo(caminho, conteudo) {
+            Ok(()) => return Ok(()),
+            Err(e) if tentativa < GRAVACAO_TENTATIVAS => {
+                warn_limitado!(
+                    "⚠️  Falha ao gravar {} ({}/{}): {:?}, nova tentativa em {}ms",
+                    caminho,
+                    tentativa,
+                    GRAVACAO_TENTATIVAS,
+                    e,
+                    backoff_ms
+                );
+                FreeRtos::delay_ms(backoff_ms);
+                backoff_ms *= 2;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    unreachable!("GRAVACAO_TENTATIVAS deve ser maior que zero")
+}
+
+// Época Unix depois que o SNTP sincronizou; antes disso, segundos desde o boot
+// (valores abaixo de `EPOCH_MINIMO_VALIDO` no arquivo indicam uptime)
+fn timestamp_gravacao() -> u64 {
+    epoch_atual().unwrap_or_else(|| (esp_idf_sys::esp_timer_get_time() / 1000000) as u64)
+}
+
+fn gravar_bmp280(dados: &DadosBMP280, buffer: &mut BufferPendente) -> std::io::Result<()> {
+    let timestamp = timestamp_gravacao();
+    let linha = format!(
+        "{},{:.2},{:.2},{:.2}\n",
+        timestamp, dados.temperatura, dados.pressao, dados.altitude
+    );
+
+    buffer.gravar(linha)?;
+
+    info!(
+        "✓ BMP280: T={:.2}°C, P={:.2}hPa, Alt={:.2}m{}",
+        dados.temperatura,
+        dados.pressao,
+        dados.altitude,
+        if dados.abaixo_referencia {
+            " (pressão acima da referência)"
+        } else {
+            ""
+        }
+    );
+
+    Ok(())
+}
+
+fn gravar_dht11(dados: &DadosDHT11, buffer: &mut BufferPendente) -> std::io::Result<()> {
+    let timestamp = timestamp_gravacao();
+    let linha = format!(
+        "{},{:.2},{:.2}\n",
+        timestamp, dados.temperatura, dados.umidade
+    );
+
+    buffer.gravar(linha)?;
+
+    info!(
+        "✓ DHT11: T={:.2}°C, RH={:.2}%",
+        dados.temperatura, dados.umidade
+    );
+
+    Ok(())
+}
+
+// ============================================
+// Publicação e Heartbeat
+// ============================================
+
+// Cliente MQTT global, inicializado depois que o WiFi conecta
+static CLIENTE_MQTT: OnceLock<Mutex<EspMqttClient<'static>>> = OnceLock::new();
+// Atualizado pelos eventos do cliente; o deep sleep espera a conexão para publicar
+static MQTT_CONECTADO: AtomicBool = AtomicBool::new(false);
+
+struct CredenciaisWifi {
+    ssid: String,
+    senha: String,
+}
+
+// Credenciais gravadas na NVS ou, no primeiro boot, o par compilado no firmware
+fn carregar_credenciais_wifi(nvs: &EspNvs<NvsDefault>) -> CredenciaisWifi {
+    // SSID tem até 32 bytes e a senha WPA2 até 64, mais o terminador
+    let mut buf_ssid = [0u8; 33];
+    let mut buf_senha = [0u8; 65];
+
+    let gravadas = nvs
+        .get_str(NVS_CHAVE_WIFI_SSID, &mut buf_ssid)
+        .and_then(|ssid| Ok((ssid, nvs.get_str(NVS_CHAVE_WIFI_SENHA, &mut buf_senha)?)));
+
+    match gravadas {
+        Ok((Some(ssid), Some(senha))) => CredenciaisWifi {
+            ssid: ssid.to_string(),
+            senha: senha.to_string(),
+        },
+        Ok(_) => {
+            info!("⚙️  Sem credenciais WiFi na NVS, usando as padrão");
+            CredenciaisWifi {
+                ssid: WIFI_SSID.to_string(),
+                senha: WIFI_SENHA.to_string(),
+            }
+        }
+        Err(e) => {
+            warn!(
+                "⚠️  Erro ao ler credenciais WiFi da NVS, usando as padrão: {:?}",
+                e
+            );
+            CredenciaisWifi {
+                ssid: WIFI_SSID.to_string(),
+                senha: WIFI_SENHA.to_string(),
+            }
+        }
+    }
+}
+
+fn conectar_wifi(
+    modem: esp_idf_svc::hal::modem::Modem,
+    sysloop: EspSystemEventLoop,
+    nvs: EspDefaultNvsPartition,
+    credenciais: &CredenciaisWifi,
+) -> anyhow::Result<BlockingWifi<EspWifi<'static>>> {
+    let mut wifi = BlockingWifi::wrap(EspWifi::new(modem, sysloop.clone(), Some(nvs))?, sysloop)?;
+
+    wifi.set_configuration(&Configuration::Client(ClientConfiguration {
+        ssid: credenciais
+            .ssid
+            .as_str()
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("SSID muito longo"))?,
+        password: credenciais
+            .senha
+            .as_str()
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("Senha muito longa"))?,
+        auth_method: AuthMethod::WPA2Personal,
+        ..Default::default()
+    }))?;
+
+    wifi.start()?;
+
+    // Roteador ainda ligando ou sinal fraco: tenta de novo com backoff exponencial
+    let mut backoff_ms = WIFI_BACKOFF_INICIAL_MS;
+    for tentativa in 1..=WIFI_TENTATIVAS {
+        match wifi.connect().and_then(|()| wifi.wait_netif_up()) {
+            Ok(()) => break,
+            Err(e) if tentativa < WIFI_TENTATIVAS => {
+                warn!(
+                    "⚠️  Falha ao conectar a {} ({}/{}): {:?}, nova tentativa em {}ms",
+                    credenciais.ssid, tentativa, WIFI_TENTATIVAS, e, backoff_ms
+                );
+                let _ = wifi.disconnect();
+                FreeRtos::delay_ms(backoff_ms);
+                backoff_ms = (backoff_ms * 2).min(WIFI_BACKOFF_MAX_MS);
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let ip = wifi.wifi().sta_netif().get_ip_info()?;
+    info!("✓ WiFi conectado a {} (IP {})", credenciais.ssid, ip.ip);
+
+    Ok(wifi)
+}
+
+fn iniciar_mqtt() -> anyhow::Result<()> {
+    let url = format!("mqtt://{}:{}", MQTT_BROKER_HOST, MQTT_BROKER_PORTA);
+    let conf = MqttClientConfiguration {
+        client_id: Some(MQTT_CLIENT_ID),
+        ..Default::default()
+    };
+
+    let cliente = EspMqttClient::new_cb(&url, &conf, |evento| {
+        match evento.payload() {
+            EventPayload::Connected(_) => MQTT_CONECTADO.store(true, Ordering::Relaxed),
+            EventPayload::Disconnected => MQTT_CONECTADO.store(false, Ordering::Relaxed),
+            _ => {}
+        }
+        debug!("MQTT: {:?}", evento.payload());
+    })?;
+
+    CLIENTE_MQTT
+        .set(Mutex::new(cliente))
+        .map_err(|_| anyhow::anyhow!("Cliente MQTT já inicializado"))?;
+    info!("✓ MQTT configurado ({})", url);
+
+    Ok(())
+}
+
+// Ponto único de saída das mensagens do firmware. Falhas são apenas
+// reportadas: quem chama decide se conta o erro e segue em frente.
+// Só as leituras podem ser retidas: um heartbeat retido faria um dashboard
+// recém-iniciado ver o dispositivo vivo mesmo com ele desligado.
+fn publicar(topico: &str, payload: &str, reter: bool) -> anyhow::Result<()> {
+    let Some(cliente) = CLIENTE_MQTT.get() else {
+        anyhow::bail!("MQTT indisponível");
+    };
+
+    cliente
+        .lock()
+        .unwrap()
+        .publish(topico, MQTT_QOS, reter, payload.as_bytes())?;
+    debug!("📤 {}: {}", topico, payload);
+
+    Ok(())
+}
+
+// Última leitura de cada sensor. As tasks leem em ritmos independentes e cada uma
+// atualiza a sua parte; a mensagem MQTT combina as duas.
+#[derive(Default)]
+struct UltimasLeituras {
+    bmp280: Option<DadosBMP280>,
+    dht11: Option<DadosDHT11>,
+    // Quando `dht11` foi lida, para a comparação de temperaturas
+    dht11_em: Option<Instant>,
+    // Tensão da bateria (V); None sem `BATERIA_ADC` ou se a leitura falhou
+    bateria: Option<f32>,
+}
+
+impl UltimasLeituras {
+    /// |BMP280 - DHT| em °C, ou None sem leitura de algum dos dois ou com a do DHT
+    /// mais velha que `DIVERGENCIA_IDADE_MAX_SEGUNDOS`
+    fn divergencia_temperatura(&self) -> Option<f32> {
+        let (bmp, dht) = (self.bmp280?, self.dht11?);
+        if self.dht11_em?.elapsed() > Duration::from_secs(DIVERGENCIA_IDADE_MAX_SEGUNDOS) {
+            return None;
+        }
+        Some((bmp.temperatura - dht.temperatura).abs())
+    }
+
+    /// Objeto único no formato do `SensorData` do servidor, ou None enquanto o BMP280
+    /// não leu. `temperatura` vem do BMP280 (0,01 °C de resolução contra 0,1 °C do
+    /// DHT11 e sem o autoaquecimento do DHT); a do DHT11 segue em `temperatura_dht11`
+    /// para o dashboard comparar. Umidade só o DHT11 mede e pressão só o BMP280; a
+    /// altitude vai junto para o dashboard reduzir a pressão ao nível do mar.
+    /// Sem leitura do DHT, ou com uma mais velha que `idade_max_dht`, `umidade` e
+    /// `temperatura_dht11` ficam de fora (o servidor aceita os dois ausentes).
+    fn payload_json(&self, idade_max_dht: Duration) -> Option<String> {
+        let bmp = self.bmp280?;
+
+        let dht = match (self.dht11, self.dht11_em) {
+            (Some(dht), Some(em)) if em.elapsed() <= idade_max_dht => format!(
+                ",\"umidade\":{:.2},\"temperatura_dht11\":{:.2}",
+                dht.umidade, dht.temperatura
+            ),
+            _ => String::new(),
+        };
+
+        // `timestamp` (época Unix) só vai junto quando o relógio já foi sincronizado
+        let timestamp = match epoch_atual() {
+            Some(epoch) => format!(",\"timestamp\":{}", epoch),
+            None => String::new(),
+        };
+
+        let bateria = match self.bateria {
+            Some(volts) => format!(",\"bateria\":{:.2}", volts),
+            None => String::new(),
+        };
+
+        Some(format!(
+            "{{\"temperatura\":{:.2},\"pressao\":{:.2},\"altitude\":{:.1}{}{}{}}}",
+            bmp.temperatura, bmp.pressao, bmp.altitude, dht, bateria, timestamp
+        ))
+    }
+}
+
+// Tentativas de leitura de um sensor desde o boot, separadas por resultado.
+// No DHT cada tentativa do intervalo conta, então `crc` mostra a qualidade do sinal
+// mesmo quando a mediana das outras salva a leitura.
+struct ContadoresLeitura {
+    ok: AtomicU32,
+    crc: AtomicU32,
+    // Timeout, erro de I2C e afins
+    outros: AtomicU32,
+}
+
+impl ContadoresLeitura {
+    fn new() -> Self {
+        Self {
+            ok: AtomicU32::new(0),
+            crc: AtomicU32::new(0),
+            outros: AtomicU32::new(0),
+        }
+    }
+
+    fn registrar<T>(&self, resultado: &Result<T, esp_idf_sys::EspError>) {
+        let contador = match resultado {
+            Ok(_) => &self.ok,
+            Err(e) if e.code() == esp_idf_sys::ESP_ERR_INVALID_CRC as esp_idf_sys::esp_err_t => {
+                &self.crc
+            }
+            Err(_) => &self.outros,
+        };
+        contador.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn json(&self) -> String {
+        format!(
+            "{{\"ok\":{},\"crc\":{},\"outros\":{}}}",
+            self.ok.load(Ordering::Relaxed),
+            self.crc.load(Ordering::Relaxed),
+            self.outros.load(Ordering::Relaxed)
+        )
+    }
+}
+
+// Contadores acumulados desde o boot, lidos pelo heartbeat
+struct Estatisticas {
+    // Intervalos sem leitura (no DHT, todas as tentativas falharam)
+    erros_bmp280: AtomicU32,
+    erros_dht11: AtomicU32,
+    erros_mqtt: AtomicU32,
+    leituras_bmp280: ContadoresLeitura,
+    leituras_umidade: ContadoresLeitura,
+    // Última comparação válida entre BMP280 e DHT (°C); None até haver leituras recentes dos dois
+    divergencia_temperatura: Mutex<Option<f32>>,
+}
+
+impl Estatisticas {
+    fn new() -> Self {
+        Self {
+            erros_bmp280: AtomicU32::new(0),
+            erros_dht11: AtomicU32::new(0),
+            erros_mqtt: AtomicU32::new(0),
+            leituras_bmp280: ContadoresLeitura::new(),
+            leituras_umidade: ContadoresLeitura::new(),
+            divergencia_temperatura: Mutex::new(None),
+        }
+    }
+}
+
+fn task_heartbeat(estatisticas: Arc<Estatisticas>) {
+    info!(
+        "🚀 Task de heartbeat iniciada (a cada {}s)",
+        HEARTBEAT_INTERVALO_SEGUNDOS
+    );
+    registrar_watchdog("heartbeat");
+
+    loop {
+        let uptime = esp_idf_sys::esp_timer_get_time() / 1000000;
+        // Sem comparação recente os dois campos ficam de fora
+        let divergencia = match *estatisticas.divergencia_temperatura.lock().unwrap() {
+            Some(delta) => format!(
+                ",\"divergencia_temperatura_c\":{:.2},\"sensores_divergentes\":{}",
+                delta,
+                delta > DIVERGENCIA_TEMPERATURA_MAX_C
+            ),
+            None => String::new(),
+        };
+        let payload = format!(
+            "{{\"uptime_s\":{},\"erros_bmp280\":{},\"erros_dht11\":{},\"erros_mqtt\":{},\"leituras_bmp280\":{},\"leituras_umidade\":{}{}}}",
+            uptime,
+            estatisticas.erros_bmp280.load(Ordering::Relaxed),
+            estatisticas.erros_dht11.load(Ordering::Relaxed),
+            estatisticas.erros_mqtt.load(Ordering::Relaxed),
+            estatisticas.leituras_bmp280.json(),
+            estatisticas.leituras_umidade.json(),
+            divergencia
+        );
+        if let Err(e) = publicar(TOPICO_HEARTBEAT, &payload, false) {
+            estatisticas.erros_mqtt.fetch_add(1, Ordering::Relaxed);
+            warn_limitado!("⚠️  Erro ao publicar heartbeat: {:?}", e);
+        }
+
+        dormir_com_watchdog(HEARTBEAT_INTERVALO_SEGUNDOS * 1000);
+    }
+}
+
+// ============================================
+// Resumo Horário
+// ============================================
+
+#[derive(Debug, Clone, Copy)]
+struct Acumulador {
+    min: f32,
+    max: f32,
+    soma: f32,
+    amostras: u32,
+}
+
+impl Acumulador {
+    fn new() -> Self {
+        Self {
+            min: f32::INFINITY,
+            max: f32::NEG_INFINITY,
+            soma: 0.0,
+            amostras: 0,
+        }
+    }
+
+    fn adicionar(&mut self, valor: f32) {
+        self.min = self.min.min(valor);
+        self.max = self.max.max(valor);
+        self.soma += valor;
+        self.amostras += 1;
+    }
+
+    fn json(&self) -> String {
+        if self.amostras == 0 {
+            return "null".to_string();
+        }
+
+        format!(
+            "{{\"min\":{:.2},\"max\":{:.2},\"media\":{:.2},\"amostras\":{}}}",
+            self.min,
+            self.max,
+            self.soma / self.amostras as f32,
+            self.amostras
+        )
+    }
+}
+
+struct ResumoHorario {
+    temperatura: Acumulador,
+    umidade: Acumulador,
+    pressao: Acumulador,
+}
+
+impl ResumoHorario {
+    fn new() -> Self {
+        Self {
+            temperatura: Acumulador::new(),
+            umidade: Acumulador::new(),
+            pressao: Acumulador::new(),
+        }
+    }
+
+    fn vazio(&self) -> bool {
+        self.temperatura.amostras == 0 && self.umidade.amostras == 0 && self.pressao.amostras == 0
+    }
+
+    fn json(&self, hora_epoch: u64) -> String {
+        format!(
+            "{{\"hora\":{},\"temperatura\":{},\"umidade\":{},\"pressao\":{}}}",
+            hora_epoch,
+            self.temperatura.json(),
+            self.umidade.json(),
+            self.pressao.json()
+        )
+    }
+}
+
+// Segundos desde a época, apenas quando o relógio já foi sincronizado
+fn epoch_atual() -> Option<u64> {
+    let segundos = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+    (segundos >= EPOCH_MINIMO_VALIDO).then_some(segundos)
+}
+
+fn task_resumo_horario(resumo: Arc<Mutex<ResumoHorario>>) {
+    info!("🚀 Task de resumo horário iniciada");
+
+    let mut hora_atual: Option<u64> = None;
+    registrar_watchdog("resumo");
+
+    loop {
+        dormir_com_watchdog(RESUMO_POLL_MS as u64);
+
+        let Some(hora) = epoch_atual().map(|s| s / 3600) else {
+            // Sem relógio válido, descarta o acumulado para não misturar janelas
+            *resumo.lock().unwrap() = ResumoHorario::new();
+            continue;
+        };
+
+        match hora_atual {
+            None => {
+                // Primeira hora com relógio válido: começa uma janela limpa
+                *resumo.lock().unwrap() = ResumoHorario::new();
+                hora_atual = Some(hora);
+            }
+            Some(anterior) if anterior != hora => {
+                let fechado = std::mem::replace(&mut *resumo.lock().unwrap(), ResumoHorario::new());
+                hora_atual = Some(hora);
+
+                if fechado.vazio() {
+                    continue;
+                }
+
+                info!("📈 Resumo horário fechado");
+                if let Err(e) =
+                    publicar(TOPICO_RESUMO_HORARIO, &fechado.json(anterior * 3600), false)
+                {
+                    warn_limitado!("⚠️  Erro ao publicar resumo horário: {:?}", e);
+                }
+            }
+            Some(_) => {}
+        }
+    }
+}
+
+// ============================================
+// Calibração de Pressão (Serial)
+// ============================================
+
+// Compartilhado entre a task do BMP280 e o console serial
+struct CalibracaoPressao {
+    offset_hpa: f32,
+    ultima_pressao_bruta: Option<f32>,
+}
+
+// Valores f32 são guardados na NVS pelos bits, como u32
+fn carregar_f32_nvs(nvs: &EspNvs<NvsDefault>, chave: &str, padrao: f32) -> f32 {
+    match nvs.get_u32(chave) {
+        Ok(Some(bits)) => f32::from_bits(bits),
+        Ok(None) => padrao,
+        Err(e) => {
+            warn!("⚠️  Erro ao ler {} da NVS: {:?}", chave, e);
+            padrao
+        }
+    }
+}
+
+fn salvar_f32_nvs(nvs: &mut Option<EspNvs<NvsDefault>>, chave: &str, valor: f32) {
+    if let Some(nvs) = nvs.as_mut() {
+        match nvs.set_u32(chave, valor.to_bits()) {
+            Ok(()) => info!("✓ {} salvo na NVS", chave),
+            Err(e) => warn!("⚠️  Erro ao salvar {} na NVS: {:?}", chave, e),
+        }
+    }
+}
+
+/// Inverso da fórmula barométrica: pressão ao nível do mar que faz `pressao_hpa`
+/// corresponder à altitude conhecida `altitude_m`.
+fn pressao_nivel_mar(pressao_hpa: f32, altitude_m: f32) -> f32 {
+    pressao_hpa / (1.0 - altitude_m / 44330.0).powf(1.0 / 0.1903)
+}
+
+fn definir_nivel_mar(hpa: f32, config: &Mutex<Config>, nvs: &mut Option<EspNvs<NvsDefault>>) {
+    let mut config = config.lock().unwrap();
+    let anterior = config.pressao_nivel_mar_hpa;
+
+    match config.set_pressao_nivel_mar(hpa) {
+        Ok(()) => {
+            info!(
+                "📐 Pressão ao nível do mar: {:.2}hPa (anterior {:.2}hPa)",
+                hpa, anterior
+            );
+            salvar_f32_nvs(nvs, NVS_CHAVE_NIVEL_MAR, hpa);
+        }
+        Err(e) => error!("❌ {}", e),
+    }
+}
+
+// ============================================
+// Configuração via HTTP
+// ============================================
+
+// Corpo de POST /config. `intervalo_minutos` vale para os dois sensores;
+// `intervalo_bmp`/`intervalo_dht` ajustam um só e têm prioridade.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct AjusteConfig {
+    intervalo_minutos: Option<u64>,
+    intervalo_bmp: Option<u64>,
+    intervalo_dht: Option<u64>,
+}
+
+impl AjusteConfig {
+    // Valida tudo antes de mudar qualquer coisa; devolve o JSON de resposta
+    fn aplicar(&self, config: &mut Config) -> anyhow::Result<String> {
+        let bmp = self
+            .intervalo_bmp
+            .or(self.intervalo_minutos)
+            .unwrap_or(config.intervalo_bmp);
+        let dht = self
+            .intervalo_dht
+            .or(self.intervalo_minutos)
+            .unwrap_or(config.intervalo_dht);
+        config.set_intervalos(bmp, dht)?;
+        Ok(format!(
+            "{{\"intervalo_bmp\":{},\"intervalo_dht\":{}}}",
+            config.intervalo_bmp, config.intervalo_dht
+        ))
+    }
+}
+
+/// Sobe o servidor HTTP com `POST /config`. O servidor para quando o valor
+/// devolvido é descartado. Os novos intervalos valem a partir do próximo ciclo
+/// de cada task, não interrompem a espera atual e não sobrevivem a um reboot.
+fn iniciar_servidor_config(config: Arc<Mutex<Config>>) -> anyhow::Result<EspHttpServer<'static>> {
+    let mut servidor = EspHttpServer::new(&ConfiguracaoHttp {
+        http_port: HTTP_CONFIG_PORTA,
+        ..Default::default()
+    })?;
+
+    servidor.fn_handler::<anyhow::Error, _>("/config", Method::Post, move |mut req| {
+        let mut corpo = [0u8; HTTP_CONFIG_CORPO_MAX];
+        let mut lidos = 0;
+        while lidos < corpo.len() {
+            let n = req.read(&mut corpo[lidos..])?;
+            if n == 0 {
+                break;
+            }
+            lidos += n;
+        }
+
+        // Corpo maior que o buffer chega truncado e falha no parse
+        let resultado = serde_json::from_slice::<AjusteConfig>(&corpo[..lidos])
+            .map_err(anyhow::Error::from)
+            .and_then(|ajuste| ajuste.aplicar(&mut config.lock().unwrap()));
+
+        match resultado {
+            Ok(resposta) => {
+                info!("⚙️  Configuração alterada via HTTP: {}", resposta);
+                req.into_response(200, None, &[("Content-Type", "application/json")])?
+                    .write_all(resposta.as_bytes())?;
+            }
+            Err(e) => {
+                warn!("⚠️  POST /config recusado: {}", e);
+                req.into_status_response(400)?
+                    .write_all(e.to_string().as_bytes())?;
+            }
+        }
+        Ok(())
+    })?;
+
+    info!(
+        "✓ Servidor HTTP de configuração na porta {}",
+        HTTP_CONFIG_PORTA
+    );
+    Ok(servidor)
+}
+
+fn processar_comando(
+    linha: &str,
+    calibracao: &Mutex<CalibracaoPressao>,
+    config: &Mutex<Config>,
+    nvs: &mut Option<EspNvs<NvsDefault>>,
+) {
+    // SSID e senha podem ter espaços, então o resto da linha tem parser próprio
+    let comando = linha.trim();
+    if let Some(argumentos) = comando
+        .strip_prefix("wifi")
+        .filter(|resto| resto.is_empty() || resto.starts_with(char::is_whitespace))
+    {
+        salvar_wifi(argumentos, nvs);
+        return;
+    }
+
+    let partes: Vec<&str> = linha.split_whitespace().collect();
+
+    match partes.as_slice() {
+        ["calibrate_pressure", referencia] => {
+            let Ok(referencia) = referencia.parse::<f32>() else {
+                error!("❌ Uso: calibrate_pressure <ref_hpa>");
+                return;
+            };
+
+            let mut cal = calibracao.lock().unwrap();
+            let Some(medida) = cal.ultima_pressao_bruta else {
+                error!("❌ Nenhuma leitura do BMP280 disponível ainda");
+                return;
+            };
+
+            let novo_offset = referencia - medida;
+            info!(
+                "📐 BMP280: medida={:.2}hPa, referência={:.2}hPa, deriva={:+.2}hPa (offset anterior {:+.2}hPa)",
+                medida, referencia, novo_offset, cal.offset_hpa
+            );
+            cal.offset_hpa = novo_offset;
+            salvar_f32_nvs(nvs, NVS_CHAVE_OFFSET_PRESSAO, novo_offset);
+        }
+        ["sea_level", hpa] => {
+            let Ok(hpa) = hpa.parse::<f32>() else {
+                error!("❌ Uso: sea_level <hpa>");
+                return;
+            };
+            definir_nivel_mar(hpa, config, nvs);
+        }
+        ["calibrate_altitude", altitude] => {
+            let Ok(altitude) = altitude.parse::<f32>() else {
+                error!("❌ Uso: calibrate_altitude <metros>");
+                return;
+            };
+
+            // Pressão já corrigida pelo offset, a mesma usada no cálculo de altitude
+            let pressao = {
+                let cal = calibracao.lock().unwrap();
+                let Some(medida) = cal.ultima_pressao_bruta else {
+                    error!("❌ Nenhuma leitura do BMP280 disponível ainda");
+                    return;
+                };
+                medida + cal.offset_hpa
+            };
+
+            info!(
+                "📐 Altitude conhecida {:.0}m com pressão {:.2}hPa",
+                altitude, pressao
+            );
+            definir_nivel_mar(pressao_nivel_mar(pressao, altitude), config, nvs);
+        }
+        ["temp_offset", sensor, graus] => {
+            let Ok(graus) = graus.parse::<f32>() else {
+                error!("❌ Uso: temp_offset <bmp280|dht11> <graus>");
+                return;
+            };
+
+            let mut config = config.lock().unwrap();
+            let (offset, chave) = match *sensor {
+                "bmp280" => (&mut config.temp_offset_bmp280, NVS_CHAVE_OFFSET_T_BMP280),
+                "dht11" => (&mut config.temp_offset_dht11, NVS_CHAVE_OFFSET_T_DHT11),
+                _ => {
+                    error!("❌ Sensor desconhecido: {} (use bmp280 ou dht11)", sensor);
+                    return;
+                }
+            };
+
+            info!(
+                "📐 Offset de temperatura do {}: {:+.2}°C (anterior {:+.2}°C)",
+                sensor, graus, offset
+            );
+            *offset = graus;
+            salvar_f32_nvs(nvs, chave, graus);
+        }
+        [] => {}
+        _ => warn!("❓ Comando desconhecido: {}", linha.trim()),
+    }
+}
+
+// `wifi <ssid> <senha>`: valores com espaços vão entre aspas ("Minha Rede"),
+// e `\` escapa o caractere seguinte (aspas ou barra dentro do valor)
+fn salvar_wifi(argumentos: &str, nvs: &mut Option<EspNvs<NvsDefault>>) {
+    let (ssid, senha) = match separar_argumentos(argumentos).as_deref() {
+        Some([ssid, senha]) => (ssid.clone(), senha.clone()),
+        _ => {
+            error!("❌ Uso: wifi <ssid> <senha> (entre aspas se tiverem espaços)");
+            return;
+        }
+    };
+
+    if ssid.is_empty() || ssid.len() > WIFI_SSID_MAX_BYTES {
+        error!(
+            "❌ SSID deve ter de 1 a {} bytes (tem {})",
+            WIFI_SSID_MAX_BYTES,
+            ssid.len()
+        );
+        return;
+    }
+    if !(WIFI_SENHA_MIN_BYTES..=WIFI_SENHA_MAX_BYTES).contains(&senha.len()) {
+        error!(
+            "❌ Senha WPA2 deve ter de {} a {} bytes (tem {})",
+            WIFI_SENHA_MIN_BYTES,
+            WIFI_SENHA_MAX_BYTES,
+            senha.len()
+        );
+        return;
+    }
+
+    let Some(nvs) = nvs.as_mut() else {
+        error!("❌ NVS desativada, credenciais WiFi não podem ser salvas");
+        return;
+    };
+
+    match nvs
+        .set_str(NVS_CHAVE_WIFI_SSID, &ssid)
+        .and_then(|()| nvs.set_str(NVS_CHAVE_WIFI_SENHA, &senha))
+    {
+        Ok(()) => info!(
+            "✓ Credenciais WiFi de {} salvas, valem no próximo boot",
+            ssid
+        ),
+        Err(e) => warn!("⚠️  Erro ao salvar credenciais WiFi na NVS: {:?}", e),
+    }
+}
+
+// Divide em argumentos separados por espaços, respeitando aspas e `\`.
+// None com aspas sem fechar ou `\` no fim da linha.
+fn separar_argumentos(texto: &str) -> Option<Vec<String>> {
+    let mut argumentos = Vec::new();
+    let mut atual: Option<String> = None;
+    let mut entre_aspas = false;
+    let mut caracteres = texto.chars();
+
+    while let Some(c) = caracteres.next() {
+        match c {
+            '\\' => atual
+                .get_or_insert_with(String::new)
+                .push(caracteres.next()?),
+            '"' => {
+                entre_aspas = !entre_aspas;
+                // "" é um argumento vazio, não ausente
+                atual.get_or_insert_with(String::new);
+            }
+            c if c.is_whitespace() && !entre_aspas => argumentos.extend(atual.take()),
+            c => atual.get_or_insert_with(String::new).push(c),
+        }
+    }
+
+    if entre_aspas {
+        return None;
+    }
+    argumentos.extend(atual);
+    Some(argumentos)
+}
+
+// Codifica um valor para a query string (percent-encoding de tudo fora dos não reservados)
+fn codificar_url(texto: &str) -> String {
+    let mut saida = String::with_capacity(texto.len());
+    for byte in texto.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                saida.push(byte as char)
+            }
+            _ => saida.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    saida
+}
+
+// Trecho do arquivo ainda não enviado: bytes [inicio, fim) e o timestamp da última linha.
+// O marcador é um timestamp e não uma posição em bytes porque `rotacionar_se_cheio` descarta
+// o começo do arquivo. Linhas sem relógio sincronizado ficam de fora (o dashboard as recusa),
+// assim como uma última linha ainda incompleta.
+fn trecho_pendente(caminho: &str, marcador: u64) -> std::io::Result<Option<(u64, u64, u64)>> {
+    let arquivo = match std::fs::File::open(caminho) {
+        Ok(arquivo) => arquivo,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut leitor = std::io::BufReader::with_capacity(INGESTAO_BLOCO_BYTES, arquivo);
+
+    let (mut posicao, mut inicio, mut fim, mut ultimo) = (0u64, None, 0u64, marcador);
+    let mut linha = String::new();
+    loop {
+        linha.clear();
+        let lidos = std::io::BufRead::read_line(&mut leitor, &mut linha)? as u64;
+        if lidos == 0 || !linha.ends_with('\n') {
+            break;
+        }
+        posicao += lidos;
+
+        let timestamp = linha
+            .split(',')
+            .next()
+            .and_then(|t| t.trim().parse::<u64>().ok());
+        if let Some(ts) = timestamp.filter(|&ts| ts > marcador && ts >= EPOCH_MINIMO_VALIDO) {
+            inicio.get_or_insert(posicao - lidos);
+            fim = posicao;
+            ultimo = ultimo.max(ts);
+        }
+    }
+
+    Ok(inicio.map(|inicio| (inicio, fim, ultimo)))
+}
+
+// Envia em blocos as linhas posteriores a `marcador` e devolve o novo marcador (None se não
+// havia nada novo). Sem gzip: o compressor deflate precisa de mais RAM do que a ESP32 tem
+// livre. Linhas gravadas durante o envio vão na próxima conexão.
+fn enviar_arquivo(
+    url: &str,
+    token: &str,
+    sensor: &str,
+    caminho: &str,
+    marcador: u64,
+) -> anyhow::Result<Option<(u64, u64)>> {
+    let Some((inicio, fim, ultimo)) = trecho_pendente(caminho, marcador)? else {
+        return Ok(None);
+    };
+    let tamanho = fim - inicio;
+
+    // O dashboard identifica o dispositivo pelo último nível do tópico
+    let dispositivo = MQTT_TOPICO.rsplit('/').next().unwrap_or(MQTT_TOPICO);
+    let uri = format!(
+        "{}?device={}&sensor={}",
+        url,
+        codificar_url(dispositivo),
+        codificar_url(sensor)
+    );
+    let comprimento = tamanho.to_string();
+    let autorizacao = format!("Bearer {}", token);
+
+    let mut arquivo = std::fs::File::open(caminho)?;
+    std::io::Seek::seek(&mut arquivo, std::io::SeekFrom::Start(inicio))?;
+
+    let mut conexao = EspHttpConnection::new(&ConfiguracaoClienteHttp {
+        timeout: Some(Duration::from_secs(INGESTAO_TIMEOUT_SEGUNDOS)),
+        ..Default::default()
+    })?;
+    conexao.initiate_request(
+        Method::Post,
+        &uri,
+        &[
+            ("Content-Type", "text/csv"),
+            ("Content-Length", &comprimento),
+            ("Authorization", &autorizacao),
+        ],
+    )?;
+
+    let mut bloco = [0u8; INGESTAO_BLOCO_BYTES];
+    let mut restante = tamanho;
+    while restante > 0 {
+        let limite = restante.min(INGESTAO_BLOCO_BYTES as u64) as usize;
+        let lidos = std::io::Read::read(&mut arquivo, &mut bloco[..limite])?;
+        // Rotação no meio do envio: a requisição ficaria menor que o Content-Length
+        if lidos == 0 {
+            anyhow::bail!("{} encolheu durante o envio", caminho);
+        }
+        conexao.write_all(&bloco[..lidos])?;
+        restante -= lidos as u64;
+    }
+
+    conexao.initiate_response()?;
+    match conexao.status() {
+        200..=299 => Ok(Some((tamanho, ultimo))),
+        status => anyhow::bail!("dashboard respondeu HTTP {}", status),
+    }
+}
+
+// Fora do watchdog: um envio lento não deve reiniciar o chip. Sem NVS o marcador fica só
+// em RAM e, depois de um reboot, o arquivo vai inteiro (o dashboard descarta as duplicatas).
+fn task_ingestao(url: &'static str, token: &'static str, mut nvs: Option<EspNvs<NvsDefault>>) {
+    info!("🚀 Task de ingestão iniciada ({})", url);
+    let mut conectado = false;
+
+    let mut arquivos = [
+        ("bmp280", ARQUIVO_BMP280, NVS_CHAVE_INGESTAO_BMP280, 0u64),
+        ("dht11", ARQUIVO_DHT11, NVS_CHAVE_INGESTAO_DHT11, 0u64),
+    ];
+    if let Some(nvs) = &nvs {
+        for (_, _, chave, marcador) in arquivos.iter_mut() {
+            match nvs.get_u64(chave) {
+                Ok(valor) => *marcador = valor.unwrap_or(0),
+                Err(e) => warn!("⚠️  Erro ao ler {} da NVS: {:?}", chave, e),
+            }
+        }
+    }
+
+    loop {
+        let agora_conectado = MQTT_CONECTADO.load(Ordering::Relaxed);
+        // Cada (re)conexão pode encerrar um período offline; na primeira, o de antes do boot
+        if agora_conectado && !conectado {
+            for (sensor, arquivo, chave, marcador) in arquivos.iter_mut() {
+                match enviar_arquivo(url, token, sensor, arquivo, *marcador) {
+                    Ok(None) => debug!("{}: nada novo a enviar", arquivo),
+                    Ok(Some((bytes, ultimo))) => {
+                        info!("✓ {} enviado ao dashboard ({} bytes novos)", arquivo, bytes);
+                        *marcador = ultimo;
+                        if let Some(Err(e)) = nvs.as_mut().map(|nvs| nvs.set_u64(chave, ultimo)) {
+                            warn!("⚠️  Erro ao salvar {} na NVS: {:?}", chave, e);
+                        }
+                    }
+                    Err(e) => warn_limitado!("⚠️  Erro ao enviar {}: {:?}", arquivo, e),
+                }
+            }
+        }
+        conectado = agora_conectado;
+
+        FreeRtos::delay_ms(INGESTAO_POLL_MS);
+    }
+}
+
+fn task_serial(
+    calibracao: Arc<Mutex<CalibracaoPressao>>,
+    config: Arc<Mutex<Config>>,
+    mut nvs: Option<EspNvs<NvsDefault>>,
+) {
+    info!(
+        "🚀 Console serial iniciado (calibrate_pressure <ref_hpa>, sea_level <hpa>, calibrate_altitude <metros>, temp_offset <bmp280|dht11> <graus>, wifi <ssid> <senha>, com aspas para espaços)"
+    );
+
+    let stdin = std::io::stdin();
+    let mut linha = String::new();
+    registrar_watchdog("serial");
+
+    loop {
+        alimentar_watchdog();
+        // A leitura do console não bloqueia: acumula até receber a linha completa
+        match stdin.read_line(&mut linha) {
+            Ok(_) if linha.ends_with('\n') => {
+                processar_comando(&linha, &calibracao, &config, &mut nvs);
+                linha.clear();
+            }
+            _ => FreeRtos::delay_ms(100),
+        }
+    }
+}
+
+// ============================================
+// Controle por Ocupação (PIR)
+// ============================================
+
+fn task_ocupacao(gpio: i32, ocupado: Arc<AtomicBool>) {
+    info!("🚀 Task de ocupação iniciada (GPIO{})", gpio);
+
+    // SAFETY: o GPIO de ocupação é exclusivo desta task
+    let pin = unsafe { AnyInputPin::new(gpio) };
+    let pin = match PinDriver::input(pin) {
+        Ok(p) => p,
+        Err(e) => {
+            error_limitado!("❌ Erro ao configurar GPIO de ocupação: {:?}", e);
+            // Sem o sensor, não bloqueia as leituras
+            ocupado.store(true, Ordering::Relaxed);
+            return;
+        }
+    };
+
+    let cooldown = Duration::from_secs(OCUPACAO_COOLDOWN_SEGUNDOS);
+    let mut ultimo_ativo: Option<Instant> = None;
+    registrar_watchdog("ocupacao");
+
+    loop {
+        alimentar_watchdog();
+        if pin.is_high() {
+            ultimo_ativo = Some(Instant::now());
+        }
+
+        // Continua ocupado até o fim do cooldown após o último sinal ativo
+        let agora_ocupado = ultimo_ativo.is_some_and(|t| t.elapsed() <= cooldown);
+
+        if agora_ocupado != ocupado.load(Ordering::Relaxed) {
+            ocupado.store(agora_ocupado, Ordering::Relaxed);
+            if agora_ocupado {
+                info!("👤 Ocupação detectada, leituras retomadas");
+            } else {
+                info!("💤 Ambiente desocupado, leituras pausadas");
+            }
+        }
+
+        FreeRtos::delay_ms(OCUPACAO_POLL_MS);
+    }
+}
+
+fn aguardar_ocupacao(ocupado: &AtomicBool) {
+    while !ocupado.load(Ordering::Relaxed) {
+        alimentar_watchdog();
+        FreeRtos::delay_ms(OCUPACAO_POLL_MS);
+    }
+}
+
+// ============================================
+// LED de Status
+// ============================================
+
+// Situação de um sensor vista pelo LED
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum EstadoSensor {
+    #[default]
+    SemLeitura,
+    Ok,
+    Falhando,
+}
+
+impl EstadoSensor {
+    // Um erro isolado mantém o estado anterior; só erros repetidos viram `Falhando`
+    fn apos_leitura(self, ok: bool, erros_consecutivos: u32) -> Self {
+        if ok {
+            EstadoSensor::Ok
+        } else if erros_consecutivos >= LED_ERROS_REPETIDOS {
+            EstadoSensor::Falhando
+        } else {
+            self
+        }
+    }
+}
+
+// Atualizado pelas tasks dos sensores, lido pela task do LED
+#[derive(Debug, Default)]
+struct StatusSensores {
+    bmp280: EstadoSensor,
+    umidade: EstadoSensor,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PadraoLed {
+    Apagado,
+    Aceso,
+    PiscaLento,
+    PiscaRapido,
+}
+
+impl PadraoLed {
+    fn de(status: &StatusSensores, conectado: bool) -> Self {
+        let sensores = [status.bmp280, status.umidade];
+        if !conectado {
+            PadraoLed::Apagado
+        } else if sensores.contains(&EstadoSensor::Falhando) {
+            PadraoLed::PiscaRapido
+        } else if sensores.iter().all(|s| *s == EstadoSensor::Ok) {
+            PadraoLed::Aceso
+        } else {
+            PadraoLed::PiscaLento
+        }
+    }
+}
+
+fn task_led(mut led: PinDriver<'static, Gpio2, Output>, status: Arc<Mutex<StatusSensores>>) {
+    info!("🚀 Task do LED de status iniciada");
+    registrar_watchdog("led");
+
+    loop {
+        alimentar_watchdog();
+        // O MQTT só conecta com WiFi, então cobre as duas quedas
+        let padrao = PadraoLed::de(
+            &status.lock().unwrap(),
+            MQTT_CONECTADO.load(Ordering::Relaxed),
+        );
+
+        let resultado = match padrao {
+            PadraoLed::Apagado => led.set_low(),
+            PadraoLed::Aceso => led.set_high(),
+            PadraoLed::PiscaLento | PadraoLed::PiscaRapido => led.toggle(),
+        };
+        if let Err(e) = resultado {
+            warn_limitado!("⚠️  Erro ao acionar LED de status: {:?}", e);
+        }
+
+        FreeRtos::delay_ms(match padrao {
+            PadraoLed::PiscaRapido => LED_PISCA_RAPIDO_MS,
+            _ => LED_PISCA_LENTO_MS,
+        });
+    }
+}
+
+// ============================================
+// Watchdog
+// ============================================
+
+/// Ajusta o timeout do task watchdog (já iniciado pelo ESP-IDF no boot, ou iniciado aqui).
+/// Dispara pânico ao estourar, e o pânico reinicia o chip. As tasks ociosas não são
+/// vigiadas: só as que chamam `registrar_watchdog`.
+fn configurar_watchdog() -> Result<(), esp_idf_sys::EspError> {
+    let config = esp_idf_sys::esp_task_wdt_config_t {
+        timeout_ms: WATCHDOG_TIMEOUT_MS,
+        idle_core_mask: 0,
+        trigger_panic: true,
+    };
+
+    // SAFETY: `config` vive até o fim das chamadas, que só copiam os valores
+    let erro = unsafe { esp_idf_sys::esp_task_wdt_reconfigure(&config) };
+    if erro == esp_idf_sys::ESP_ERR_INVALID_STATE as esp_idf_sys::esp_err_t {
+        esp_idf_sys::EspError::convert(unsafe { esp_idf_sys::esp_task_wdt_init(&config) })
+    } else {
+        esp_idf_sys::EspError::convert(erro)
+    }
+}
+
+/// Inscreve a task atual no watchdog; a partir daqui ela precisa chamar
+/// `alimentar_watchdog` a cada volta do loop
+fn registrar_watchdog(nome: &str) {
+    // SAFETY: handle nulo significa a própria task que está chamando
+    let erro = unsafe { esp_idf_sys::esp_task_wdt_add(std::ptr::null_mut()) };
+    if let Err(e) = esp_idf_sys::EspError::convert(erro) {
+        warn_limitado!("⚠️  Task {} fora do watchdog: {:?}", nome, e);
+    }
+}
+
+fn alimentar_watchdog() {
+    // SAFETY: sem pré-condições; em task não registrada só devolve erro
+    unsafe {
+        esp_idf_sys::esp_task_wdt_reset();
+    }
+}
+
+// Dorme `ms` alimentando o watchdog a cada WATCHDOG_FATIA_MS
+fn dormir_com_watchdog(ms: u64) {
+    let mut restante = ms;
+    while restante > 0 {
+        alimentar_watchdog();
+        let fatia = restante.min(WATCHDOG_FATIA_MS);
+        thread::sleep(Duration::from_millis(fatia));
+        restante -= fatia;
+    }
+    alimentar_watchdog();
+}
+
+// ============================================
+// Tasks Assíncronas
+// ============================================
+
+fn task_bmp280(
+    config: Arc<Mutex<Config>>,
+    i2c: BarramentoCompartilhado,
+    ocupado: Arc<AtomicBool>,
+    resumo: Arc<Mutex<ResumoHorario>>,
+    calibracao: Arc<Mutex<CalibracaoPressao>>,
+    estatisticas: Arc<Estatisticas>,
+    ultimas: Arc<Mutex<UltimasLeituras>>,
+    mut bateria: Option<MonitorBateria>,
+    status: Arc<Mutex<StatusSensores>>,
+) {
+    info!("🚀 Task BMP280 iniciada");
+
+    let ajustes = match config.lock().unwrap().ajustes_bmp280() {
+        Ok(a) => a,
+        Err(e) => {
+            error_limitado!("❌ Configuração inválida do BMP280: {}", e);
+            return;
+        }
+    };
+    info!(
+        "   BMP280 em modo {}, osrs_t={:?}, osrs_p={:?}, filtro={:?}, média de {} pressões",
+        if ajustes.modo_forcado {
+            "forçado"
+        } else {
+            "normal"
+        },
+        ajustes.osrs_t,
+        ajustes.osrs_p,
+        ajustes.filtro,
+        ajustes.janela_media_pressao
+    );
+
+    let mut sensor = match BMP280::detectar(i2c, ajustes) {
+        Ok(s) => s,
+        Err(e) => {
+            error_limitado!("❌ Erro ao inicializar BMP280: {:?}", e);
+            return;
+        }
+    };
+
+    let mut contador_erros = 0;
+    const MAX_ERROS: u32 = 5;
+    let mut buffer = BufferPendente::new(ARQUIVO_BMP280);
+    let mut deadband = FiltroDeadband::new([DEADBAND_TEMPERATURA_C, DEADBAND_PRESSAO_HPA]);
+    registrar_watchdog("bmp280");
+
+    loop {
+        alimentar_watchdog();
+        aguardar_ocupacao(&ocupado);
+
+        // O offset pode mudar a qualquer momento pelo console serial
+        sensor.offset_pressao_hpa = calibracao.lock().unwrap().offset_hpa;
+        {
+            let config = config.lock().unwrap();
+            sensor.pressao_nivel_mar_hpa = config.pressao_nivel_mar_hpa;
+            sensor.offset_temperatura_c = config.temp_offset_bmp280;
+        }
+
+        let resultado = sensor.ler_dados();
+        estatisticas.leituras_bmp280.registrar(&resultado);
+        match resultado {
+            Ok(dados) => {
+                calibracao.lock().unwrap().ultima_pressao_bruta = Some(dados.pressao_bruta);
+
+                {
+                    let mut resumo = resumo.lock().unwrap();
+                    resumo.temperatura.adicionar(dados.temperatura);
+                    resumo.pressao.adicionar(dados.pressao);
+                }
+
+                if !deadband.deve_gravar([dados.temperatura, dados.pressao]) {
+                    debug!("BMP280: variação dentro do deadband, leitura não gravada");
+                } else if let Err(e) = gravar_bmp280(&dados, &mut buffer) {
+                    warn_limitado!("⚠️  Erro ao gravar BMP280 (mantida em buffer): {:?}", e);
+                }
+
+                // A bateria vai junto com a leitura publicada por esta task
+                let volts = bateria
+                    .as_mut()
+                    .and_then(|monitor| match monitor.ler_volts() {
+                        Ok(v) => Some(v),
+                        Err(e) => {
+                            warn_limitado!("⚠️  Erro ao ler bateria: {:?}", e);
+                            None
+                        }
+                    });
+
+                // Uma mensagem por leitura do BMP280, com a última do DHT se ainda for recente
+                let idade_max_dht = Duration::from_millis(
+                    config.lock().unwrap().intervalo_dht_ms() * DHT_PUBLICACAO_INTERVALOS_MAX,
+                );
+                let (payload, divergencia) = {
+                    let mut ultimas = ultimas.lock().unwrap();
+                    ultimas.bmp280 = Some(dados);
+                    ultimas.bateria = volts;
+                    (
+                        ultimas.payload_json(idade_max_dht),
+                        ultimas.divergencia_temperatura(),
+                    )
+                };
+                if let Some(delta) = divergencia {
+                    *estatisticas.divergencia_temperatura.lock().unwrap() = Some(delta);
+                    if delta > DIVERGENCIA_TEMPERATURA_MAX_C {
+                        warn_limitado!(
+                            "⚠️  BMP280 e DHT divergem em {:.1} °C (tolerância {:.1} °C): autoaquecimento ou sensor com defeito?",
+                            delta,
+                            DIVERGENCIA_TEMPERATURA_MAX_C
+                        );
+                    }
+                }
+                if let Some(payload) = payload {
+                    if let Err(e) = publicar(MQTT_TOPICO, &payload, MQTT_RETER_LEITURAS) {
+                        estatisticas.erros_mqtt.fetch_add(1, Ordering::Relaxed);
+                        warn_limitado!("⚠️  Erro ao publicar leitura: {:?}", e);
+                    }
+                }
+                contador_erros = 0;
+                let mut status = status.lock().unwrap();
+                status.bmp280 = status.bmp280.apos_leitura(true, 0);
+            }
+            Err(e) => {
+                contador_erros += 1;
+                estatisticas.erros_bmp280.fetch_add(1, Ordering::Relaxed);
+                {
+                    let mut status = status.lock().unwrap();
+                    status.bmp280 = status.bmp280.apos_leitura(false, contador_erros);
+                }
+                warn_limitado!(
+                    "⚠️  Erro ao ler BMP280 ({}/{}): {:?}",
+                    contador_erros,
+                    MAX_ERROS,
+                    e
+                );
+
+                if contador_erros >= MAX_ERROS {
+                    error_limitado!("❌ BMP280: Muitos erros consecutivos, reiniciando sensor...");
+                    FreeRtos::delay_ms(1000);
+                    // Tentar reinicializar
+                    match BMP280::new(Arc::clone(&sensor.i2c), sensor.addr, ajustes) {
+                        Ok(s) => {
+                            sensor = s;
+                            contador_erros = 0;
+                            info!("✓ BMP280 reinicializado");
+                        }
+                        Err(e) => {
+                            error_limitado!("❌ Falha ao reinicializar BMP280: {:?}", e);
+                        }
+                    }
+                }
+            }
+        }
+
+        let intervalo = config.lock().unwrap().intervalo_bmp_ms();
+        dormir_com_watchdog(intervalo);
+    }
+}
+
+fn task_dht11(
+    config: Arc<Mutex<Config>>,
+    gpio4: Gpio4,
+    i2c: BarramentoCompartilhado,
+    ocupado: Arc<AtomicBool>,
+    resumo: Arc<Mutex<ResumoHorario>>,
+    estatisticas: Arc<Estatisticas>,
+    ultimas: Arc<Mutex<UltimasLeituras>>,
+    status: Arc<Mutex<StatusSensores>>,
+) {
+    info!("🚀 Task DHT11 iniciada");
+
+    let abertura = SensorUmidade::abrir(&config.lock().unwrap(), gpio4, i2c);
+    let mut sensor = match abertura {
+        Ok(s) => s,
+        Err(e) => {
+            error_limitado!("❌ Erro ao inicializar sensor de umidade: {:?}", e);
+            return;
+        }
+    };
+    let nome = sensor.nome();
+
+    let mut contador_erros = 0;
+    const MAX_ERROS: u32 = 5;
+    let mut buffer = BufferPendente::new(ARQUIVO_DHT11);
+    let mut deadband = FiltroDeadband::new([DEADBAND_TEMPERATURA_C, DEADBAND_UMIDADE_PCT]);
+    registrar_watchdog("dht11");
+
+    loop {
+        alimentar_watchdog();
+        aguardar_ocupacao(&ocupado);
+
+        sensor.set_offset_temperatura(config.lock().unwrap().temp_offset_dht11);
+
+        match sensor.ler_dados(&estatisticas.leituras_umidade) {
+            Ok(dados) => {
+                resumo.lock().unwrap().umidade.adicionar(dados.umidade);
+                {
+                    let mut ultimas = ultimas.lock().unwrap();
+                    ultimas.dht11 = Some(dados);
+                    ultimas.dht11_em = Some(Instant::now());
+                }
+
+                if !deadband.deve_gravar([dados.temperatura, dados.umidade]) {
+                    debug!("{}: variação dentro do deadband, leitura não gravada", nome);
+                } else if let Err(e) = gravar_dht11(&dados, &mut buffer) {
+                    warn_limitado!("⚠️  Erro ao gravar {} (mantida em buffer): {:?}", nome, e);
+                }
+                contador_erros = 0;
+                let mut status = status.lock().unwrap();
+                status.umidade = status.umidade.apos_leitura(true, 0);
+            }
+            Err(e) => {
+                contador_erros += 1;
+                estatisticas.erros_dht11.fetch_add(1, Ordering::Relaxed);
+                {
+                    let mut status = status.lock().unwrap();
+                    status.umidade = status.umidade.apos_leitura(false, contador_erros);
+                }
+                warn_limitado!(
+                    "⚠️  Erro ao ler {} ({}/{}): {:?}",
+                    nome,
+                    contador_erros,
+                    MAX_ERROS,
+                    e
+                );
+
+                if contador_erros >= MAX_ERROS {
+                    error_limitado!("❌ {}: Muitos erros consecutivos", nome);
+                    contador_erros = 0;
+                }
+            }
+        }
+
+        let intervalo = config.lock().unwrap().intervalo_dht_ms();
+        dormir_com_watchdog(intervalo);
+    }
+}
+
+// ============================================
+// Deep Sleep
+// ============================================
+
+/// Um ciclo completo no modo deep sleep: lê os dois sensores uma vez, grava no SPIFFS,
+/// publica e dorme pelo intervalo. O ESP32 reinicia do zero ao acordar, então o buffer
+/// pendente e o deadband não sobrevivem entre ciclos. Não retorna.
+fn ciclo_deep_sleep(
+    config: &Mutex<Config>,
+    i2c: BarramentoCompartilhado,
+    gpio4: Gpio4,
+    calibracao: &Mutex<CalibracaoPressao>,
+    bateria: Option<MonitorBateria>,
+) -> anyhow::Result<()> {
+    let config = config.lock().unwrap().clone();
+    let mut ultimas = UltimasLeituras::default();
+
+    // Falhas de um sensor não impedem o outro nem o próximo ciclo
+    match config.ajustes_bmp280() {
+        Ok(ajustes) => match BMP280::detectar(Arc::clone(&i2c), ajustes) {
+            Ok(mut sensor) => {
+                sensor.offset_pressao_hpa = calibracao.lock().unwrap().offset_hpa;
+                sensor.pressao_nivel_mar_hpa = config.pressao_nivel_mar_hpa;
+                sensor.offset_temperatura_c = config.temp_offset_bmp280;
+
+                match sensor.ler_dados() {
+                    Ok(dados) => {
+                        if let Err(e) =
+                            gravar_bmp280(&dados, &mut BufferPendente::new(ARQUIVO_BMP280))
+                        {
+                            warn!("⚠️  Erro ao gravar BMP280: {:?}", e);
+                        }
+                        ultimas.bmp280 = Some(dados);
+                    }
+                    Err(e) => warn!("⚠️  Erro ao ler BMP280: {:?}", e),
+                }
+            }
+            Err(e) => error!("❌ Erro ao inicializar BMP280: {:?}", e),
+        },
+        Err(e) => error!("❌ Configuração inválida do BMP280: {}", e),
+    }
+
+    match SensorUmidade::abrir(&config, gpio4, i2c) {
+        Ok(mut sensor) => {
+            sensor.set_offset_temperatura(config.temp_offset_dht11);
+
+            // Sem heartbeat no deep sleep: os contadores não são publicados
+            match sensor.ler_dados(&ContadoresLeitura::new()) {
+                Ok(dados) => {
+                    if let Err(e) = gravar_dht11(&dados, &mut BufferPendente::new(ARQUIVO_DHT11)) {
+                        warn!("⚠️  Erro ao gravar {}: {:?}", sensor.nome(), e);
+                    }
+                    ultimas.dht11 = Some(dados);
+                    ultimas.dht11_em = Some(Instant::now());
+                }
+                Err(e) => warn!("⚠️  Erro ao ler {}: {:?}", sensor.nome(), e),
+            }
+        }
+        Err(e) => error!("❌ Erro ao inicializar sensor de umidade: {:?}", e),
+    }
+
+    if let Some(mut monitor) = bateria {
+        match monitor.ler_volts() {
+            Ok(volts) => ultimas.bateria = Some(volts),
+            Err(e) => warn!("⚠️  Erro ao ler bateria: {:?}", e),
+        }
+    }
+
+    // Leituras do próprio ciclo: qualquer idade serve
+    match ultimas.payload_json(Duration::MAX) {
+        Some(payload) => {
+            let inicio = Instant::now();
+            let limite = Duration::from_millis(DEEP_SLEEP_ESPERA_MQTT_MS as u64);
+            while !MQTT_CONECTADO.load(Ordering::Relaxed) && inicio.elapsed() < limite {
+                FreeRtos::delay_ms(100);
+            }
+
+            match publicar(MQTT_TOPICO, &payload, MQTT_RETER_LEITURAS) {
+                // Dá tempo ao cliente de entregar a mensagem antes de desligar o rádio
+                Ok(()) => FreeRtos::delay_ms(DEEP_SLEEP_ENVIO_MS),
+                Err(e) => warn!("⚠️  Erro ao publicar leitura: {:?}", e),
+            }
+        }
+        None => info!("MQTT: sem leitura do BMP280, nada publicado neste ciclo"),
+    }
+
+    let minutos = config.intervalo_deep_sleep_minutos();
+    let intervalo_us = minutos * 60 * 1000 * 1000;
+    info!("💤 Deep sleep por {} minutos", minutos);
+
+    // SAFETY: chamadas do ESP-IDF sem pré-condições; o chip reinicia ao acordar
+    unsafe {
+        esp_idf_sys::esp_sleep_enable_timer_wakeup(intervalo_us);
+        esp_idf_sys::esp_deep_sleep_start()
+    }
+}
+
+// ============================================
+// Main
+// ============================================
+
+fn main() -> anyhow::Result<()> {
+    esp_idf_svc::sys::link_patches();
+    esp_idf_svc::log::EspLogger::initialize_default();
+    log::set_max_level(NIVEL_LOG);
+
+    info!("╔════════════════════════════════════════╗");
+    info!("║  Sistema de Leitura de Sensores       ║");
+    info!("║  ESP32-S3 + BMP280 + DHT11            ║");
+    info!("╚════════════════════════════════════════╝");
+
+    // Sem watchdog a estação segue funcionando, só não se recupera de um travamento
+    match configurar_watchdog() {
+        Ok(()) => info!("⚙️  Watchdog: {} s por task", WATCHDOG_TIMEOUT_MS / 1000),
+        Err(e) => warn!("⚠️  Erro ao configurar watchdog: {:?}", e),
+    }
+
+    let config = Arc::new(Mutex::new(Config::new()));
+    let peripherals = Peripherals::take()?;
+
+    // NVS guarda a calibração de pressão entre reinicializações
+    let nvs_particao = EspDefaultNvsPartition::take()?;
+    let nvs = EspNvs::new(nvs_particao.clone(), NVS_NAMESPACE, true)?;
+    // Handle próprio da task de ingestão; a partição em si vai para o WiFi
+    let particao_ingestao = nvs_particao.clone();
+    let offset_pressao = carregar_f32_nvs(&nvs, NVS_CHAVE_OFFSET_PRESSAO, 0.0);
+    info!("⚙️  Offset de pressão: {:+.2} hPa", offset_pressao);
+
+    let nivel_mar = carregar_f32_nvs(&nvs, NVS_CHAVE_NIVEL_MAR, PRESSAO_REFERENCIA_HPA);
+    if let Err(e) = config.lock().unwrap().set_pressao_nivel_mar(nivel_mar) {
+        warn!("⚠️  Valor salvo na NVS ignorado: {}", e);
+    }
+    info!(
+        "⚙️  Pressão ao nível do mar: {:.2} hPa",
+        config.lock().unwrap().pressao_nivel_mar_hpa
+    );
+
+    {
+        let mut config = config.lock().unwrap();
+        config.temp_offset_bmp280 =
+            carregar_f32_nvs(&nvs, NVS_CHAVE_OFFSET_T_BMP280, OFFSET_TEMPERATURA_BMP280_C);
+        config.temp_offset_dht11 =
+            carregar_f32_nvs(&nvs, NVS_CHAVE_OFFSET_T_DHT11, OFFSET_TEMPERATURA_DHT11_C);
+        info!(
+            "⚙️  Offsets de temperatura: BMP280 {:+.2} °C, DHT11 {:+.2} °C",
+            config.temp_offset_bmp280, config.temp_offset_dht11
+        );
+    }
+
+    let calibracao = Arc::new(Mutex::new(CalibracaoPressao {
+        offset_hpa: offset_pressao,
+        ultima_pressao_bruta: None,
+    }));
+
+    // Sem WiFi/MQTT o firmware continua gravando no SPIFFS
+    info!("⚙️  Conectando ao WiFi...");
+    let sysloop = EspSystemEventLoop::take()?;
+    let credenciais = carregar_credenciais_wifi(&nvs);
+    let wifi = match conectar_wifi(peripherals.modem, sysloop, nvs_particao, &credenciais) {
+        Ok(wifi) => {
+            if let Err(e) = iniciar_mqtt() {
+                warn!("⚠️  Erro ao iniciar MQTT: {:?}", e);
+            }
+            Some(wifi)
+        }
+        Err(e) => {
+            warn!("⚠️  WiFi indisponível, apenas SPIFFS: {:?}", e);
+            None
+        }
+    };
+
+    // O SNTP sincroniza em segundo plano; até lá os timestamps são uptime
+    let _sntp = match &wifi {
+        Some(_) => match EspSntp::new_default() {
+            Ok(sntp) => {
+                info!("✓ SNTP iniciado");
+                Some(sntp)
+            }
+            Err(e) => {
+                warn!("⚠️  Erro ao iniciar SNTP, timestamps em uptime: {:?}", e);
+                None
+            }
+        },
+        None => None,
+    };
+
+    // Configurar barramentos I2C
+    info!("⚙️  Configurando I2C...");
+    let i2c0 = criar_barramento_i2c(
+        "i2c0",
+        peripherals.i2c0,
+        I2C_SDA_GPIO,
+        I2C_SCL_GPIO,
+        I2C_FREQUENCIA_KHZ,
+    )?;
+
+    let i2c1 = match I2C1_PINOS {
+        Some((sda, scl)) => {
+            if [sda, scl]
+                .iter()
+                .any(|p| [I2C_SDA_GPIO, I2C_SCL_GPIO].contains(p))
+            {
+                anyhow::bail!("Pinos do i2c1 conflitam com o i2c0");
+            }
+            Some(criar_barramento_i2c(
+                "i2c1",
+                peripherals.i2c1,
+                sda,
+                scl,
+                I2C1_FREQUENCIA_KHZ,
+            )?)
+        }
+        None => None,
+    };
+
+    let i2c = match BMP280_BARRAMENTO {
+        BarramentoI2c::I2c0 => Arc::clone(&i2c0),
+        BarramentoI2c::I2c1 => match &i2c1 {
+            Some(barramento) => Arc::clone(barramento),
+            None => anyhow::bail!("BMP280 configurado no i2c1, mas I2C1_PINOS não está definido"),
+        },
+    };
+    info!("   BMP280 no {:?}", BMP280_BARRAMENTO);
+
+    info!("⚙️  Escaneando I2C...");
+    let no_i2c0 = escanear_i2c("i2c0", &i2c0);
+    let no_i2c1 = i2c1.as_ref().map(|b| escanear_i2c("i2c1", b));
+    let no_barramento_bmp = match BMP280_BARRAMENTO {
+        BarramentoI2c::I2c0 => Some(&no_i2c0),
+        BarramentoI2c::I2c1 => no_i2c1.as_ref(),
+    };
+    if no_barramento_bmp
+        .is_some_and(|enderecos| !BMP280_ENDERECOS.iter().any(|addr| enderecos.contains(addr)))
+    {
+        warn!(
+            "⚠️  Nada respondeu em {:02X?} no {:?}: confira a ligação do BMP280",
+            BMP280_ENDERECOS, BMP280_BARRAMENTO
+        );
+    }
+
+    info!(
+        "⚙️  Configurando GPIO para {}...",
+        config.lock().unwrap().modelo_dht.nome()
+    );
+    let gpio4 = peripherals.pins.gpio4;
+
+    let bateria = if BATERIA_ADC {
+        match MonitorBateria::new(peripherals.adc1, peripherals.pins.gpio1) {
+            Ok(monitor) => Some(monitor),
+            Err(e) => {
+                warn!("⚠️  Erro ao inicializar monitor de bateria: {:?}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let led = if LED_STATUS {
+        match PinDriver::output(peripherals.pins.gpio2) {
+            Ok(pin) => Some(pin),
+            Err(e) => {
+                warn!("⚠️  Erro ao configurar LED de status: {:?}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // No deep sleep não há tasks: um ciclo por boot
+    if config.lock().unwrap().deep_sleep {
+        info!("⚙️  Modo deep sleep");
+        return ciclo_deep_sleep(&config, i2c, gpio4, &calibracao, bateria);
+    }
+
+    let _servidor_config = match &wifi {
+        Some(_) => match iniciar_servidor_config(Arc::clone(&config)) {
+            Ok(servidor) => Some(servidor),
+            Err(e) => {
+                warn!("⚠️  Erro ao iniciar servidor HTTP de configuração: {:?}", e);
+                None
+            }
+        },
+        None => None,
+    };
+
+    match (&wifi, INGESTAO_URL, INGESTAO_TOKEN) {
+        (Some(_), Some(url), Some(token)) => {
+            let nvs_ingestao = match EspNvs::new(particao_ingestao, NVS_NAMESPACE, true) {
+                Ok(nvs) => Some(nvs),
+                Err(e) => {
+                    warn!("⚠️  NVS indisponível para o backfill: {:?}", e);
+                    None
+                }
+            };
+            thread::Builder::new()
+                .stack_size(STACK_INGESTAO)
+                .name("ingestao".to_string())
+                .spawn(move || task_ingestao(url, token, nvs_ingestao))?;
+        }
+        (Some(_), Some(_), None) => {
+            warn!("⚠️  INGESTAO_URL sem INGESTAO_TOKEN: backfill desativado")
+        }
+        _ => {}
+    }
+
+    // Sem sensor de presença, as leituras nunca são bloqueadas
+    let ocupado = Arc::new(AtomicBool::new(OCUPACAO_GPIO.is_none()));
+
+    if let Some(gpio) = OCUPACAO_GPIO {
+        info!(
+            "⚙️  Configurando sensor de ocupação (GPIO{}, cooldown {}s)...",
+            gpio, OCUPACAO_COOLDOWN_SEGUNDOS
+        );
+        let ocupado_pir = Arc::clone(&ocupado);
+        thread::Builder::new()
+            .stack_size(STACK_OCUPACAO)
+            .name("ocupacao".to_string())
+            .spawn(move || task_ocupacao(gpio, ocupado_pir))?;
+    }
+
+    // Criar threads
+    info!(
+        "⚙️  Pilhas das tasks: bmp280={} B, dht11={} B, ocupacao={} B, resumo={} B, serial={} B, heartbeat={} B, led={} B, ingestao={} B",
+        STACK_BMP280,
+        STACK_DHT11,
+        STACK_OCUPACAO,
+        STACK_RESUMO,
+        STACK_SERIAL,
+        STACK_HEARTBEAT,
+        STACK_LED,
+        STACK_INGESTAO
+    );
+
+    let status = Arc::new(Mutex::new(StatusSensores::default()));
+    if let Some(led) = led {
+        let status_led = Arc::clone(&status);
+        thread::Builder::new()
+            .stack_size(STACK_LED)
+            .name("led".to_string())
+            .spawn(move || task_led(led, status_led))?;
+    }
+
+    let estatisticas = Arc::new(Estatisticas::new());
+    let estatisticas_hb = Arc::clone(&estatisticas);
+    thread::Builder::new()
+        .stack_size(STACK_HEARTBEAT)
+        .name("heartbeat".to_string())
+        .spawn(move || task_heartbeat(estatisticas_hb))?;
+
+    let calibracao_serial = Arc::clone(&calibracao);
+    let config_serial = Arc::clone(&config);
+    let nvs_serial = PERSISTIR_CALIBRACAO_NVS.then_some(nvs);
+    thread::Builder::new()
+        .stack_size(STACK_SERIAL)
+        .name("serial".to_string())
+        .spawn(move || task_serial(calibracao_serial, config_serial, nvs_serial))?;
+
+    let resumo = Arc::new(Mutex::new(ResumoHorario::new()));
+    let resumo_task = Arc::clone(&resumo);
+    thread::Builder::new()
+        .stack_size(STACK_RESUMO)
+        .name("resumo".to_string())
+        .spawn(move || task_resumo_horario(resumo_task))?;
+
+    let config_bmp = Arc::clone(&config);
+    let i2c_bmp = Arc::clone(&i2c);
+    let ocupado_bmp = Arc::clone(&ocupado);
+    let resumo_bmp = Arc::clone(&resumo);
+    let calibracao_bmp = Arc::clone(&calibracao);
+    let estatisticas_bmp = Arc::clone(&estatisticas);
+    let ultimas = Arc::new(Mutex::new(UltimasLeituras::default()));
+    let ultimas_bmp = Arc::clone(&ultimas);
+    let status_bmp = Arc::clone(&status);
+
+    let handle_bmp = thread::Builder::new()
+        .stack_size(STACK_BMP280)
+        .name("bmp280".to_string())
+        .spawn(move || {
+            task_bmp280(
+                config_bmp,
+                i2c_bmp,
+                ocupado_bmp,
+                resumo_bmp,
+                calibracao_bmp,
+                estatisticas_bmp,
+                ultimas_bmp,
+                bateria,
+                status_bmp,
+            )
+        })?;
+
+    let config_dht = Arc::clone(&config);
+    let i2c_dht = Arc::clone(&i2c);
+    let ocupado_dht = Arc::clone(&ocupado);
+    let resumo_dht = Arc::clone(&resumo);
+    let estatisticas_dht = Arc::clone(&estatisticas);
+    let handle_dht = thread::Builder::new()
+        .stack_size(STACK_DHT11)
+        .name("dht11".to_string())
+        .spawn(move || {
+            task_dht11(
+                config_dht,
+                gpio4,
+                i2c_dht,
+                ocupado_dht,
+                resumo_dht,
+                estatisticas_dht,
+                ultimas,
+                status,
+            )
+        })?;
+
+    info!("✓ Sistema iniciado!");
+    {
+        let config = config.lock().unwrap();
+        info!(
+            "📊 Intervalo de leitura: BMP280 a cada {} min, umidade a cada {} min",
+            config.intervalo_bmp, config.intervalo_dht
+        );
+    }
+    info!(
+        "📡 MQTT: {}:{} ({})",
+        MQTT_BROKER_HOST, MQTT_BROKER_PORTA, MQTT_TOPICO
     );
-    println!("📁 Arquivos de dados:");
-    println!("   - {}", ARQUIVO_BMP280);
-    println!("   - {}\n", ARQUIVO_DHT11);
+    info!("📁 Arquivos de dados:");
+    info!("   - {}", ARQUIVO_BMP280);
+    info!("   - {}", ARQUIVO_DHT11);
 
     // Aguardar threads
     handle_bmp.join().unwrap();